@@ -0,0 +1,274 @@
+use super::http::{date, Request, StatusCode};
+use serde::Deserialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Result as IoResult, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `127.0.0.1 - - [06/Nov/1994:08:49:37 +0000] "GET / HTTP/1.1" 200 512`
+    Common,
+    /// Common Log Format followed by the quoted referer and user agent.
+    Combined,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Writes one line per served request.
+#[derive(Debug)]
+pub struct AccessLog {
+    format: LogFormat,
+    output: Mutex<Output>,
+}
+
+#[derive(Debug)]
+enum Output {
+    Stdout,
+    File(RotatingFile),
+}
+
+/// What is known about a request by the time its response has gone out.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub remote_addr: Option<IpAddr>,
+    pub time: SystemTime,
+    pub request: RequestInfo,
+    pub status: StatusCode,
+    pub bytes: u64,
+    pub duration: Duration,
+}
+
+/// The parts of a request worth logging, copied out so the request's
+/// buffer can be reused before the line is written. Left empty when the
+/// request couldn't be parsed.
+#[derive(Debug, Clone, Default)]
+pub struct RequestInfo {
+    pub method: Option<String>,
+    pub target: Option<String>,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl RequestInfo {
+    pub fn from_request(request: &Request) -> Self {
+        Self {
+            method: Some(request.method().to_string()),
+            target: Some(request.target().to_string()),
+            referer: request.header("Referer").map(str::to_string),
+            user_agent: request.header("User-Agent").map(str::to_string),
+        }
+    }
+}
+
+impl AccessLog {
+    pub fn stdout(format: LogFormat) -> Self {
+        Self {
+            format,
+            output: Mutex::new(Output::Stdout),
+        }
+    }
+
+    /// Appends to the file at `path`. Once it would grow past `max_size`
+    /// bytes it is renamed to `path.1`, older files shift up by one, and
+    /// anything past `path.{max_files}` is deleted.
+    pub fn file(format: LogFormat, path: impl Into<PathBuf>, max_size: u64, max_files: usize) -> IoResult<Self> {
+        Ok(Self {
+            format,
+            output: Mutex::new(Output::File(RotatingFile::open(path.into(), max_size, max_files)?)),
+        })
+    }
+
+    pub fn log(&self, entry: &Entry) {
+        let mut line = self.format(entry);
+        line.push('\n');
+
+        let mut output = self.output.lock().unwrap_or_else(|e| e.into_inner());
+        let written = match &mut *output {
+            Output::Stdout => io::stdout().lock().write_all(line.as_bytes()),
+            Output::File(file) => file.write_all(line.as_bytes()),
+        };
+        if let Err(e) = written {
+            println!("Failed to write access log: {e}");
+        }
+    }
+
+    pub fn format(&self, entry: &Entry) -> String {
+        match self.format {
+            LogFormat::Common => common(entry),
+            LogFormat::Combined => format!(
+                "{} \"{}\" \"{}\"",
+                common(entry),
+                entry.request.referer.as_deref().map_or("-".into(), quote),
+                entry.request.user_agent.as_deref().map_or("-".into(), quote)
+            ),
+            LogFormat::Json => json(entry),
+        }
+    }
+}
+
+fn common(entry: &Entry) -> String {
+    let remote_addr = entry.remote_addr.map_or("-".to_string(), |addr| addr.to_string());
+    let request_line = match (&entry.request.method, &entry.request.target) {
+        (Some(method), Some(target)) => quote(&format!("{method} {target} HTTP/1.1")),
+        _ => "-".to_string(),
+    };
+    let bytes = match entry.bytes {
+        0 => "-".to_string(),
+        bytes => bytes.to_string(),
+    };
+    format!(
+        "{remote_addr} - - [{}] \"{request_line}\" {} {bytes}",
+        date::format_clf(entry.time),
+        entry.status
+    )
+}
+
+fn json(entry: &Entry) -> String {
+    let string = |value: Option<&str>| serde_json::to_string(&value).unwrap_or_default();
+    let remote_addr = entry.remote_addr.map(|addr| addr.to_string());
+    format!(
+        "{{\"time\":\"{}\",\"remote_addr\":{},\"method\":{},\"path\":{},\"status\":{},\"bytes\":{},\"duration_ms\":{:.3},\"referer\":{},\"user_agent\":{}}}",
+        date::format_rfc3339(entry.time),
+        string(remote_addr.as_deref()),
+        string(entry.request.method.as_deref()),
+        string(entry.request.target.as_deref()),
+        entry.status,
+        entry.bytes,
+        entry.duration.as_secs_f64() * 1000.0,
+        string(entry.request.referer.as_deref()),
+        string(entry.request.user_agent.as_deref()),
+    )
+}
+
+/// Escapes a value for the inside of a quoted log field, so a client can't
+/// end the field early or start a fake log line.
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            c if c.is_control() => quoted.push_str(&format!("\\x{:02x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted
+}
+
+#[derive(Debug)]
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, max_files: usize) -> IoResult<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> IoResult<()> {
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated(self.max_files));
+            for n in (1..self.max_files).rev() {
+                let from = self.rotated(n);
+                if Path::new(&from).exists() {
+                    fs::rename(from, self.rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        *self = Self::open(self.path.clone(), self.max_size, self.max_files)?;
+        Ok(())
+    }
+
+    fn write_all(&mut self, line: &[u8]) -> IoResult<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+    use std::net::Ipv4Addr;
+    use std::time::UNIX_EPOCH;
+
+    fn entry() -> Entry {
+        Entry {
+            remote_addr: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            time: UNIX_EPOCH + Duration::from_secs(784_111_777),
+            request: RequestInfo {
+                method: Some("GET".to_string()),
+                target: Some("/a?b=1".to_string()),
+                referer: None,
+                user_agent: Some("curl/8 \"test\"".to_string()),
+            },
+            status: StatusCode::OK,
+            bytes: 512,
+            duration: Duration::from_micros(1500),
+        }
+    }
+
+    #[test]
+    fn formats_common_combined_and_json() {
+        let common = "127.0.0.1 - - [06/Nov/1994:08:49:37 +0000] \"GET /a?b=1 HTTP/1.1\" 200 512";
+
+        assert_eq!(AccessLog::stdout(LogFormat::Common).format(&entry()), common);
+        assert_eq!(
+            AccessLog::stdout(LogFormat::Combined).format(&entry()),
+            format!("{common} \"-\" \"curl/8 \\\"test\\\"\"")
+        );
+        assert_eq!(
+            AccessLog::stdout(LogFormat::Json).format(&entry()),
+            "{\"time\":\"1994-11-06T08:49:37Z\",\"remote_addr\":\"127.0.0.1\",\"method\":\"GET\",\"path\":\"/a?b=1\",\"status\":200,\"bytes\":512,\"duration_ms\":1.500,\"referer\":null,\"user_agent\":\"curl/8 \\\"test\\\"\"}"
+        );
+    }
+
+    #[test]
+    fn rotates_files_past_max_size() {
+        let dir = temp_dir("access_log");
+        let path = dir.join("access.log");
+        let log = AccessLog::file(LogFormat::Common, &path, 100, 2).unwrap();
+
+        for _ in 0..4 {
+            log.log(&entry());
+        }
+
+        let lines = |path: &Path| fs::read_to_string(path).map_or(0, |s| s.lines().count());
+        assert_eq!(lines(&path), 1);
+        assert_eq!(lines(&dir.join("access.log.1")), 1);
+        assert_eq!(lines(&dir.join("access.log.2")), 1);
+        assert!(!dir.join("access.log.3").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
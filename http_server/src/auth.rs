@@ -0,0 +1,392 @@
+use super::http::{Request, Response, StatusCode};
+use super::middleware::{Middleware, Next};
+use argon2::password_hash::{PasswordHash, PasswordVerifier};
+use argon2::Argon2;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, KeyInit, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::io::Result as IoResult;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Puts path prefixes behind Basic or Bearer authentication.
+///
+/// The longest matching prefix decides; paths under no prefix pass through.
+/// A request that authenticates reaches the handler with `Request::user`
+/// set, and anything else is answered with 401 and a `WWW-Authenticate`
+/// challenge.
+///
+/// ```ignore
+/// let auth = Auth::new()
+///     .basic("/admin", "Admin", Htpasswd::from_file(".htpasswd")?)
+///     .bearer("/api", "API", BearerAuth::jwt(secret));
+/// let handler = Chain::new(WebsiteHandler::new(public_path)).wrap(auth);
+/// ```
+#[derive(Debug, Default)]
+pub struct Auth {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    prefix: String,
+    realm: String,
+    scheme: Scheme,
+}
+
+#[derive(Debug)]
+enum Scheme {
+    Basic(Htpasswd),
+    Bearer(BearerAuth),
+}
+
+/// Users and password hashes in the `user:hash` lines of an htpasswd file.
+/// Only bcrypt (`$2y$`, `$2b$`, `$2a$`) and argon2 (`$argon2id$`, ...)
+/// hashes are supported; lines with other hashes are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct Htpasswd {
+    users: HashMap<String, String>,
+}
+
+/// How bearer tokens are checked.
+#[derive(Debug, Clone)]
+pub enum BearerAuth {
+    /// Known tokens, each mapped to the name requests using it act as.
+    Tokens(HashMap<String, String>),
+    /// HS256-signed JWTs. `exp` and `nbf` are enforced with some leeway
+    /// for clock skew, and `sub` becomes the user.
+    Jwt { secret: Vec<u8>, leeway: Duration },
+}
+
+impl Auth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requires a user and password from `htpasswd` under `prefix`.
+    pub fn basic(mut self, prefix: &str, realm: &str, htpasswd: Htpasswd) -> Self {
+        self.rules.push(Rule {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            realm: realm.to_string(),
+            scheme: Scheme::Basic(htpasswd),
+        });
+        self
+    }
+
+    /// Requires a bearer token accepted by `bearer` under `prefix`.
+    pub fn bearer(mut self, prefix: &str, realm: &str, bearer: BearerAuth) -> Self {
+        self.rules.push(Rule {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            realm: realm.to_string(),
+            scheme: Scheme::Bearer(bearer),
+        });
+        self
+    }
+
+    /// Prefixes match case-insensitively, since a case-insensitive file
+    /// system serves `/ADMIN` from the same place as `/admin`.
+    fn rule_for(&self, path: &str) -> Option<&Rule> {
+        self.rules
+            .iter()
+            .filter(|rule| {
+                path.get(..rule.prefix.len())
+                    .is_some_and(|start| start.eq_ignore_ascii_case(&rule.prefix))
+                    && path[rule.prefix.len()..].chars().next().is_none_or(|c| c == '/')
+            })
+            .max_by_key(|rule| rule.prefix.len())
+    }
+}
+
+impl Middleware for Auth {
+    fn handle(&self, request: Request<'_>, next: Next<'_>) -> Response {
+        // Match on the path the file system will see, so `/public/../admin`
+        // or `//admin` can't slip past the rule for `/admin`, and hand the
+        // handler that same path so it can't resolve to anything else.
+        let path = normalize(request.path());
+        let request = match path == request.path() {
+            true => request,
+            false => request.with_path(path),
+        };
+        let Some(rule) = self.rule_for(request.path()) else {
+            return next.run(request);
+        };
+
+        let authorization = request.header("Authorization");
+        let (user, challenge) = match &rule.scheme {
+            Scheme::Basic(htpasswd) => (
+                authorization.and_then(basic_credentials).and_then(|(user, password)| {
+                    htpasswd.verify(&user, &password).then_some(user)
+                }),
+                format!("Basic realm=\"{}\", charset=\"UTF-8\"", rule.realm),
+            ),
+            Scheme::Bearer(bearer) => {
+                let token = authorization.and_then(bearer_token);
+                let challenge = match token {
+                    Some(_) => format!("Bearer realm=\"{}\", error=\"invalid_token\"", rule.realm),
+                    None => format!("Bearer realm=\"{}\"", rule.realm),
+                };
+                (token.and_then(|token| bearer.verify(token)), challenge)
+            }
+        };
+
+        match user {
+            Some(user) => next.run(request.with_user(user)),
+            None => {
+                let mut response = Response::error(StatusCode::Unauthorized);
+                response.headers_mut().insert("WWW-Authenticate", challenge);
+                response
+            }
+        }
+    }
+}
+
+impl Htpasswd {
+    pub fn from_file(path: impl AsRef<Path>) -> IoResult<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        let users = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (user, hash) = line.split_once(':')?;
+                if !is_supported_hash(hash) {
+                    println!("Skipping htpasswd entry for {user}: unsupported hash");
+                    return None;
+                }
+                Some((user.to_string(), hash.to_string()))
+            })
+            .collect();
+        Self { users }
+    }
+
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        let Some(hash) = self.users.get(user) else {
+            // Hashing anyway keeps unknown users from answering faster than
+            // known ones with a wrong password.
+            bcrypt::verify(password, DUMMY_HASH).ok();
+            return false;
+        };
+        if hash.starts_with("$argon2") {
+            PasswordHash::new(hash).is_ok_and(|hash| {
+                Argon2::default().verify_password(password.as_bytes(), &hash).is_ok()
+            })
+        } else {
+            bcrypt::verify(password, hash).unwrap_or(false)
+        }
+    }
+}
+
+/// A bcrypt hash at the default cost that no password is checked against
+/// for real.
+const DUMMY_HASH: &str = "$2b$12$5WV8Ke2Ey4H5Rc6parJ79eEwIaDlubOqZlxDf/RjurHYhBzIlW1S6";
+
+fn is_supported_hash(hash: &str) -> bool {
+    ["$2y$", "$2b$", "$2a$", "$argon2"].iter().any(|prefix| hash.starts_with(prefix))
+}
+
+impl BearerAuth {
+    /// Accepts each token in `tokens`, given as `(name, token)` pairs.
+    pub fn tokens(tokens: Vec<(String, String)>) -> Self {
+        Self::Tokens(tokens.into_iter().map(|(name, token)| (token, name)).collect())
+    }
+
+    /// Accepts JWTs signed with HS256 under `secret`.
+    pub fn jwt(secret: impl Into<Vec<u8>>) -> Self {
+        Self::Jwt {
+            secret: secret.into(),
+            leeway: Duration::from_secs(60),
+        }
+    }
+
+    /// The user `token` stands for, if it is valid.
+    pub fn verify(&self, token: &str) -> Option<String> {
+        match self {
+            // Every token is compared, each in constant time, so timing
+            // doesn't reveal how much of a guess was right.
+            Self::Tokens(tokens) => tokens
+                .iter()
+                .fold(None, |found, (known, name)| {
+                    let matches = constant_time_eq(known.as_bytes(), token.as_bytes());
+                    found.or(matches.then(|| name.clone()))
+                }),
+            Self::Jwt { secret, leeway } => verify_jwt(token, secret, *leeway),
+        }
+    }
+}
+
+fn verify_jwt(token: &str, secret: &[u8], leeway: Duration) -> Option<String> {
+    let (signed, signature) = token.rsplit_once('.')?;
+    let (header, payload) = signed.split_once('.')?;
+    if payload.contains('.') {
+        return None;
+    }
+
+    // Only HS256 is accepted, whatever the token claims; that rules out
+    // `none` and algorithm confusion.
+    let header: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header).ok()?).ok()?;
+    if header.get("alg")?.as_str()? != "HS256" {
+        return None;
+    }
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).ok()?;
+    mac.update(signed.as_bytes());
+    mac.verify_slice(&URL_SAFE_NO_PAD.decode(signature).ok()?).ok()?;
+
+    let claims: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let leeway = leeway.as_secs();
+    if let Some(exp) = claims.get("exp") && exp.as_u64()?.saturating_add(leeway) <= now {
+        return None;
+    }
+    if let Some(nbf) = claims.get("nbf") && nbf.as_u64()? > now.saturating_add(leeway) {
+        return None;
+    }
+    // A token that names no user authenticates nobody.
+    claims.get("sub").and_then(Value::as_str).filter(|sub| !sub.is_empty()).map(str::to_string)
+}
+
+fn basic_credentials(authorization: &str) -> Option<(String, String)> {
+    let (scheme, credentials) = authorization.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(credentials.trim()).ok()?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+fn bearer_token(authorization: &str) -> Option<&str> {
+    let (scheme, token) = authorization.trim().split_once(' ')?;
+    scheme.eq_ignore_ascii_case("Bearer").then_some(token.trim())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Resolves `.` and `..` segments and repeated slashes in a path, keeping
+/// a trailing slash.
+fn normalize(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    let trailing_slash = path.ends_with('/') && !segments.is_empty();
+    format!("/{}{}", segments.join("/"), if trailing_slash { "/" } else { "" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::Chain;
+    use crate::server::Handler;
+
+    fn whoami(request: &Request) -> Response {
+        Response::new(StatusCode::OK, Some(request.user().unwrap_or("anonymous").as_bytes().to_vec()))
+    }
+
+    fn path(request: &Request) -> Response {
+        Response::new(StatusCode::OK, Some(request.path().as_bytes().to_vec()))
+    }
+
+    fn send(handler: &impl Handler, path: &str, authorization: Option<&str>) -> Response {
+        let authorization = authorization.map_or(String::new(), |value| format!("Authorization: {value}\r\n"));
+        let raw = format!("GET {path} HTTP/1.1\r\n{authorization}\r\n");
+        handler.handle_request(&Request::try_from(raw.as_bytes()).unwrap())
+    }
+
+    fn jwt(secret: &[u8], claims: &str) -> String {
+        let signed = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#),
+            URL_SAFE_NO_PAD.encode(claims)
+        );
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+        mac.update(signed.as_bytes());
+        format!("{signed}.{}", URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+    }
+
+    #[test]
+    fn basic_auth_guards_its_prefix() {
+        let hash = bcrypt::hash("s3cret", 4).unwrap();
+        let auth = Auth::new().basic("/admin", "Admin", Htpasswd::parse(&format!("alice:{hash}\n")));
+        let chain = Chain::new(whoami).wrap(auth);
+        let good = format!("Basic {}", STANDARD.encode("alice:s3cret"));
+        let bad = format!("Basic {}", STANDARD.encode("alice:wrong"));
+
+        let allowed = send(&chain, "/admin/x", Some(&good));
+        let refused = send(&chain, "/admin/x", Some(&bad));
+        let sneaky = send(&chain, "/public/../admin/x", None);
+        let open = send(&chain, "/administrator", None);
+
+        assert_eq!(allowed.body().and_then(|body| body.as_bytes()), Some(&b"alice"[..]));
+        assert_eq!(refused.status_code(), StatusCode::Unauthorized);
+        assert_eq!(refused.headers().get("WWW-Authenticate"), Some("Basic realm=\"Admin\", charset=\"UTF-8\""));
+        assert_eq!(sneaky.status_code(), StatusCode::Unauthorized);
+        assert_eq!(open.status_code(), StatusCode::OK);
+    }
+
+    #[test]
+    fn unknown_users_still_cost_a_hash() {
+        let htpasswd = Htpasswd::parse("");
+        let started = std::time::Instant::now();
+        assert!(!htpasswd.verify("nobody", "guess"));
+        // Even on a fast machine a cost-12 bcrypt takes well over this.
+        assert!(started.elapsed() > Duration::from_millis(20));
+    }
+
+    #[test]
+    fn non_canonical_paths_cannot_skip_auth() {
+        let auth = Auth::new().basic("/admin", "Admin", Htpasswd::default());
+        let chain = Chain::new(path).wrap(auth);
+
+        for sneaky in [
+            "//admin/secret.html",
+            "/./admin/secret.html",
+            "/x/../admin/secret.html",
+            "/%2Fadmin/secret.html",
+            "/ADMIN/secret.html",
+            "/Admin",
+        ] {
+            assert_eq!(send(&chain, sneaky, None).status_code(), StatusCode::Unauthorized, "{sneaky}");
+        }
+        let routed = send(&chain, "/public/./a//b/../c/", None);
+        assert_eq!(routed.body().and_then(|body| body.as_bytes()), Some(&b"/public/a/c/"[..]));
+    }
+
+    #[test]
+    fn bearer_auth_checks_jwt_signature_and_expiry() {
+        let secret = b"jwt-secret";
+        let auth = Auth::new().bearer("/api", "API", BearerAuth::jwt(secret.to_vec()));
+        let chain = Chain::new(whoami).wrap(auth);
+        let valid = jwt(secret, r#"{"sub":"bob","exp":99999999999}"#);
+        let expired = jwt(secret, r#"{"sub":"bob","exp":1000}"#);
+        let forged = jwt(b"other", r#"{"sub":"bob"}"#);
+        let far_off = jwt(secret, &format!(r#"{{"sub":"bob","exp":{},"nbf":1}}"#, u64::MAX));
+        let anonymous = jwt(secret, r#"{"exp":99999999999}"#);
+        let nameless = jwt(secret, r#"{"sub":""}"#);
+
+        let allowed = send(&chain, "/api/items", Some(&format!("Bearer {valid}")));
+        let missing = send(&chain, "/api/items", None);
+
+        assert_eq!(allowed.body().and_then(|body| body.as_bytes()), Some(&b"bob"[..]));
+        assert_eq!(missing.headers().get("WWW-Authenticate"), Some("Bearer realm=\"API\""));
+        let far_off = send(&chain, "/api/items", Some(&format!("Bearer {far_off}")));
+        assert_eq!(far_off.body().and_then(|body| body.as_bytes()), Some(&b"bob"[..]));
+        for token in [expired, forged, anonymous, nameless] {
+            let refused = send(&chain, "/api/items", Some(&format!("Bearer {token}")));
+            assert_eq!(refused.status_code(), StatusCode::Unauthorized);
+        }
+    }
+}
@@ -0,0 +1,606 @@
+use super::access_log::{AccessLog, LogFormat};
+use super::middleware::{self, Chain};
+use super::server::{self, Server};
+use super::shutdown::ShutdownHandle;
+use super::tls::{self, TlsConfig};
+use super::virtual_host::{self, VirtualHosts};
+use super::website_handler::WebsiteHandler;
+use serde::de::IntoDeserializer;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Where the server listens when nothing says otherwise.
+const DEFAULT_LISTEN: &str = "127.0.0.1:8180";
+/// Where `TLS_CERT` and `TLS_KEY` serve HTTPS.
+const DEFAULT_TLS_LISTEN: &str = "127.0.0.1:8443";
+/// Size an access log file may grow to by default before it is rotated.
+const DEFAULT_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+/// Rotated access log files kept by default.
+const DEFAULT_LOG_MAX_FILES: usize = 5;
+
+pub const USAGE: &str = "\
+Usage: http_server [options]
+
+Options:
+  --config <file>       Read settings from a .toml, .yaml or .yml file
+  --listen <addr>       Listen for plain HTTP on <addr>; repeat for more
+  --workers <n>         Serve up to <n> connections at once per address
+  --public-dir <dir>    Serve files from <dir> for the first host
+  --log-format <fmt>    Write the access log as common, combined or json
+  --verbosity <n>       0 for errors only, 2 to dump raw requests
+  --check-config        Check the settings and exit
+  --help                Show this message
+
+Only the settings above can be given as options; they override the file's.
+Everything else, such as timeouts, TLS, log files and further hosts, is set
+in the --config file.";
+
+/// The server's settings, read from a TOML or YAML file with the same
+/// shape:
+///
+/// ```toml
+/// listen = ["0.0.0.0:80"]
+/// workers = 16
+///
+/// [timeouts]
+/// keep_alive = 5
+///
+/// [tls]
+/// listen = ["0.0.0.0:443"]
+/// cert = "cert.pem"
+/// key = "key.pem"
+/// redirect_http = true
+///
+/// [log]
+/// format = "json"
+/// file = "/var/log/http_server/access.log"
+///
+/// [[hosts]]
+/// names = ["example.com", "www.example.com"]
+/// public_dir = "sites/example"
+/// ```
+///
+/// Timeouts are in seconds. Relative paths are taken from the file's
+/// directory. Requests for a host no entry names go to the first host.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Plain HTTP addresses.
+    pub listen: Vec<String>,
+    /// Workers per address.
+    pub workers: usize,
+    /// Accepted connections that may wait for a worker, per address.
+    pub queue_size: usize,
+    pub timeouts: Timeouts,
+    pub tls: Option<Tls>,
+    pub log: Log,
+    pub hosts: Vec<Host>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    pub keep_alive: u64,
+    pub header_read: u64,
+    pub body_read: u64,
+    pub write: u64,
+    pub shutdown: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    /// HTTPS addresses.
+    pub listen: Vec<String>,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Whether the plain HTTP addresses redirect to HTTPS instead of
+    /// serving the sites.
+    #[serde(default)]
+    pub redirect_http: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Log {
+    pub format: LogFormat,
+    /// Standard output if unset.
+    pub file: Option<PathBuf>,
+    pub max_size: u64,
+    pub max_files: usize,
+    pub verbosity: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Host {
+    #[serde(default)]
+    pub names: Vec<String>,
+    pub public_dir: PathBuf,
+    #[serde(default)]
+    pub directory_listing: bool,
+}
+
+/// What went wrong loading the settings.
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    /// The file isn't valid TOML or YAML, or doesn't have the expected
+    /// shape. The message says where.
+    Parse(PathBuf, String),
+    /// A setting, named by its path such as `hosts[1].public_dir` or a
+    /// command-line flag, has a value that can't be used.
+    Invalid { key: String, message: String },
+}
+
+impl ConfigError {
+    fn invalid(key: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::Invalid {
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Read(path, e) => write!(f, "{}: {e}", path.display()),
+            Self::Parse(path, message) => write!(f, "{}: {}", path.display(), message.trim_end()),
+            Self::Invalid { key, message } => write!(f, "{key}: {message}"),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Settings given on the command line, which win over the file's.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Args {
+    pub config: Option<PathBuf>,
+    pub check_config: bool,
+    pub help: bool,
+    listen: Vec<String>,
+    workers: Option<usize>,
+    public_dir: Option<PathBuf>,
+    log_format: Option<LogFormat>,
+    verbosity: Option<u8>,
+}
+
+impl Args {
+    /// Parses the arguments after the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, ConfigError> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| ConfigError::invalid(&flag, "needs a value"))
+            };
+            match flag.as_str() {
+                "--config" => parsed.config = Some(value()?.into()),
+                "--listen" => parsed.listen.push(value()?),
+                "--workers" => parsed.workers = Some(parse_flag(&flag, &value()?)?),
+                "--public-dir" => parsed.public_dir = Some(value()?.into()),
+                "--log-format" => {
+                    let format = value()?;
+                    let format = LogFormat::deserialize(format.as_str().into_deserializer())
+                        .map_err(|e: serde::de::value::Error| ConfigError::invalid(&flag, e.to_string()))?;
+                    parsed.log_format = Some(format);
+                }
+                "--verbosity" => parsed.verbosity = Some(parse_flag(&flag, &value()?)?),
+                "--check-config" | "--help" | "-h" if inline.is_some() => {
+                    return Err(ConfigError::invalid(flag, "takes no value"));
+                }
+                "--check-config" => parsed.check_config = true,
+                "--help" | "-h" => parsed.help = true,
+                _ => return Err(ConfigError::invalid(flag, "unknown option")),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+fn parse_flag<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, ConfigError> {
+    value
+        .parse()
+        .map_err(|_| ConfigError::invalid(flag, format!("`{value}` is not a valid number")))
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: vec![DEFAULT_LISTEN.to_string()],
+            workers: server::DEFAULT_WORKERS,
+            queue_size: server::DEFAULT_QUEUE_SIZE,
+            timeouts: Timeouts::default(),
+            tls: None,
+            log: Log::default(),
+            hosts: Vec::new(),
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            keep_alive: server::DEFAULT_KEEP_ALIVE_TIMEOUT.as_secs(),
+            header_read: server::DEFAULT_HEADER_READ_TIMEOUT.as_secs(),
+            body_read: server::DEFAULT_BODY_READ_TIMEOUT.as_secs(),
+            write: server::DEFAULT_WRITE_TIMEOUT.as_secs(),
+            shutdown: server::DEFAULT_SHUTDOWN_TIMEOUT.as_secs(),
+        }
+    }
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Combined,
+            file: None,
+            max_size: DEFAULT_LOG_MAX_SIZE,
+            max_files: DEFAULT_LOG_MAX_FILES,
+            verbosity: 1,
+        }
+    }
+}
+
+impl Config {
+    /// Reads `path` as YAML if it ends in `.yaml` or `.yml`, and as TOML
+    /// otherwise.
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        let yaml = path
+            .extension()
+            .is_some_and(|extension| extension == "yaml" || extension == "yml");
+        let mut config: Self = match yaml {
+            true => serde_yaml::from_str(&contents).map_err(|e| ConfigError::Parse(path.to_path_buf(), e.to_string()))?,
+            false => toml::from_str(&contents).map_err(|e| ConfigError::Parse(path.to_path_buf(), e.to_string()))?,
+        };
+        if let Some(dir) = path.parent() {
+            config.resolve_paths(dir);
+        }
+        Ok(config)
+    }
+
+    /// The file named by `args`, if any, then the `PUBLIC_PATH`,
+    /// `VERBOSITY`, `TLS_CERT` and `TLS_KEY` environment variables, then
+    /// the rest of `args`, checked with `validate`.
+    pub fn load(args: &Args) -> Result<Self, ConfigError> {
+        let mut config = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+
+        if config.hosts.is_empty() {
+            let public_dir = env::var("PUBLIC_PATH").unwrap_or(format!("{}/public", env!("CARGO_MANIFEST_DIR")));
+            config.hosts.push(Host {
+                names: Vec::new(),
+                public_dir: public_dir.into(),
+                directory_listing: false,
+            });
+        }
+        if let Some(verbosity) = env::var("VERBOSITY").ok().and_then(|v| v.parse().ok()) {
+            config.log.verbosity = verbosity;
+        }
+        if config.tls.is_none() && let (Ok(cert), Ok(key)) = (env::var("TLS_CERT"), env::var("TLS_KEY")) {
+            config.tls = Some(Tls {
+                listen: vec![DEFAULT_TLS_LISTEN.to_string()],
+                cert: cert.into(),
+                key: key.into(),
+                redirect_http: true,
+            });
+        }
+
+        if !args.listen.is_empty() {
+            config.listen = args.listen.clone();
+        }
+        if let Some(workers) = args.workers {
+            config.workers = workers;
+        }
+        if let Some(public_dir) = &args.public_dir {
+            config.hosts[0].public_dir = public_dir.clone();
+        }
+        if let Some(format) = args.log_format {
+            config.log.format = format;
+        }
+        if let Some(verbosity) = args.verbosity {
+            config.log.verbosity = verbosity;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn resolve_paths(&mut self, dir: &Path) {
+        let resolve = |path: &mut PathBuf| {
+            if path.is_relative() {
+                *path = dir.join(&*path);
+            }
+        };
+        for host in &mut self.hosts {
+            resolve(&mut host.public_dir);
+        }
+        if let Some(tls) = &mut self.tls {
+            resolve(&mut tls.cert);
+            resolve(&mut tls.key);
+        }
+        if let Some(file) = &mut self.log.file {
+            resolve(file);
+        }
+    }
+
+    /// Checks everything that can be checked without binding: addresses
+    /// resolve, numbers are in range, directories exist and the TLS
+    /// certificate loads.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let tls_listen = self.tls.as_ref().map_or(&[][..], |tls| &tls.listen[..]);
+        if self.listen.is_empty() && tls_listen.is_empty() {
+            return Err(ConfigError::invalid("listen", "no addresses to listen on"));
+        }
+        let mut seen = HashMap::new();
+        let listen = self.listen.iter().enumerate().map(|(i, addr)| (format!("listen[{i}]"), addr));
+        let tls_listen = tls_listen.iter().enumerate().map(|(i, addr)| (format!("tls.listen[{i}]"), addr));
+        for (key, addr) in listen.chain(tls_listen) {
+            match addr.to_socket_addrs().map(|mut addrs| addrs.next().is_some()) {
+                Ok(true) => {}
+                Ok(false) => return Err(ConfigError::invalid(key, format!("`{addr}` resolves to no address"))),
+                Err(e) => return Err(ConfigError::invalid(key, format!("`{addr}` is not a valid address: {e}"))),
+            }
+            if let Some(first) = seen.insert(addr, key.clone()) {
+                return Err(ConfigError::invalid(key, format!("`{addr}` is already used by {first}")));
+            }
+        }
+
+        if self.workers == 0 {
+            return Err(ConfigError::invalid("workers", "must be at least 1"));
+        }
+        if self.queue_size == 0 {
+            return Err(ConfigError::invalid("queue_size", "must be at least 1"));
+        }
+        let timeouts = &self.timeouts;
+        for (key, secs) in [
+            ("keep_alive", timeouts.keep_alive),
+            ("header_read", timeouts.header_read),
+            ("body_read", timeouts.body_read),
+            ("write", timeouts.write),
+        ] {
+            if secs == 0 {
+                return Err(ConfigError::invalid(format!("timeouts.{key}"), "must be at least 1 second"));
+            }
+        }
+
+        if let Some(tls) = &self.tls {
+            if tls.listen.is_empty() {
+                return Err(ConfigError::invalid("tls.listen", "no addresses to listen on"));
+            }
+            for (key, path) in [("tls.cert", &tls.cert), ("tls.key", &tls.key)] {
+                fs::metadata(path).map_err(|e| ConfigError::invalid(key, format!("{}: {e}", path.display())))?;
+            }
+            self.tls_config()?;
+        }
+
+        if let Some(file) = &self.log.file {
+            let dir = file.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            if !dir.is_dir() {
+                return Err(ConfigError::invalid("log.file", format!("{} is not a directory", dir.display())));
+            }
+            if self.log.max_files == 0 {
+                return Err(ConfigError::invalid("log.max_files", "must be at least 1"));
+            }
+        }
+
+        if self.hosts.is_empty() {
+            return Err(ConfigError::invalid("hosts", "no hosts to serve"));
+        }
+        let mut names = HashMap::new();
+        for (i, host) in self.hosts.iter().enumerate() {
+            if !host.public_dir.is_dir() {
+                let message = format!("{} is not a directory", host.public_dir.display());
+                return Err(ConfigError::invalid(format!("hosts[{i}].public_dir"), message));
+            }
+            for name in &host.names {
+                if let Some(first) = names.insert(virtual_host::normalize(name), i) {
+                    let message = format!("`{name}` is already used by hosts[{first}]");
+                    return Err(ConfigError::invalid(format!("hosts[{i}].names"), message));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn tls_config(&self) -> Result<Option<TlsConfig>, ConfigError> {
+        let Some(tls) = &self.tls else {
+            return Ok(None);
+        };
+        let read = |key: &str, path: &Path| {
+            fs::read(path).map_err(|e| ConfigError::invalid(key, format!("{}: {e}", path.display())))
+        };
+        let (cert_pem, key_pem) = (read("tls.cert", &tls.cert)?, read("tls.key", &tls.key)?);
+        // Checked one at a time so an error names the file at fault.
+        tls::load_certs(&cert_pem).map_err(|e| ConfigError::invalid("tls.cert", format!("{}: {e}", tls.cert.display())))?;
+        tls::load_key(&key_pem).map_err(|e| ConfigError::invalid("tls.key", format!("{}: {e}", tls.key.display())))?;
+        TlsConfig::from_pem(&cert_pem, &key_pem)
+            .map(Some)
+            .map_err(|e| ConfigError::invalid("tls.key", format!("{}: {e} in {}", tls.key.display(), tls.cert.display())))
+    }
+
+    /// A server for every address, all stopped by `shutdown`. When HTTPS
+    /// redirects plain HTTP, the first HTTPS server answers the plain
+    /// addresses too.
+    pub fn servers(&self, shutdown: &ShutdownHandle) -> Result<Vec<Server>, ConfigError> {
+        let log = &self.log;
+        let access_log = Arc::new(match &log.file {
+            Some(file) => AccessLog::file(log.format, file, log.max_size, log.max_files)
+                .map_err(|e| ConfigError::invalid("log.file", format!("{}: {e}", file.display())))?,
+            None => AccessLog::stdout(log.format),
+        });
+        let timeouts = &self.timeouts;
+        let server = |addr: &String| {
+            Server::new(addr.clone())
+                .with_workers(self.workers)
+                .with_queue_size(self.queue_size)
+                .with_keep_alive_timeout(Duration::from_secs(timeouts.keep_alive))
+                .with_read_timeouts(Duration::from_secs(timeouts.header_read), Duration::from_secs(timeouts.body_read))
+                .with_write_timeout(Duration::from_secs(timeouts.write))
+                .with_shutdown_timeout(Duration::from_secs(timeouts.shutdown))
+                .with_access_log(Arc::clone(&access_log))
+                .with_verbosity(log.verbosity)
+                .with_shutdown_handle(shutdown.clone())
+        };
+
+        let mut servers = Vec::new();
+        let redirect_http = self.tls.as_ref().is_some_and(|tls| tls.redirect_http);
+        if let (Some(tls), Some(tls_config)) = (&self.tls, self.tls_config()?) {
+            for (i, addr) in tls.listen.iter().enumerate() {
+                let mut https = server(addr).with_tls(tls_config.clone());
+                if i == 0 && redirect_http {
+                    https = self.listen.iter().fold(https, |https, addr| https.with_https_redirect(addr.clone()));
+                }
+                servers.push(https);
+            }
+        }
+        if !redirect_http {
+            servers.extend(self.listen.iter().map(server));
+        }
+        Ok(servers)
+    }
+
+    /// What every server runs: the hosts' sites behind the standard
+    /// middleware.
+    pub fn handler(&self) -> Chain<VirtualHosts> {
+        let hosts = self.hosts.iter().fold(VirtualHosts::new(), |hosts, host| {
+            // WebsiteHandler compares canonical paths against its root.
+            let public_dir = fs::canonicalize(&host.public_dir).unwrap_or(host.public_dir.clone());
+            let website = WebsiteHandler::new(public_dir.to_string_lossy().into_owned())
+                .with_directory_listing(host.directory_listing);
+            hosts.host(host.names.clone(), website)
+        });
+        Chain::new(hosts)
+            .wrap(middleware::catch_panic)
+            .wrap(middleware::request_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    const TOML: &str = r#"
+listen = ["127.0.0.1:8080"]
+workers = 4
+
+[timeouts]
+keep_alive = 2
+
+[log]
+format = "json"
+
+[[hosts]]
+names = ["example.com"]
+public_dir = "public"
+"#;
+
+    const YAML: &str = "
+listen: [127.0.0.1:8080]
+workers: 4
+timeouts:
+  keep_alive: 2
+log:
+  format: json
+hosts:
+  - names: [example.com]
+    public_dir: public
+";
+
+    fn write(dir: &Path, name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_toml_and_yaml_alike() {
+        let dir = temp_dir("config-formats");
+        let toml = Config::from_file(&write(&dir, "config.toml", TOML)).unwrap();
+        let yaml = Config::from_file(&write(&dir, "config.yaml", YAML)).unwrap();
+
+        assert_eq!(toml, yaml);
+        assert_eq!(toml.timeouts.keep_alive, 2);
+        assert_eq!(toml.timeouts.write, server::DEFAULT_WRITE_TIMEOUT.as_secs());
+        assert_eq!(toml.log.format, LogFormat::Json);
+        assert_eq!(toml.hosts[0].public_dir, dir.join("public"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn names_the_offending_key() {
+        let dir = temp_dir("config-errors");
+        let error = Config::from_file(&write(&dir, "typo.toml", "wrkers = 4\n")).unwrap_err();
+        assert!(error.to_string().contains("unknown field `wrkers`"));
+
+        let args = Args::parse(["--config".to_string(), write(&dir, "bad.toml", TOML).display().to_string()]).unwrap();
+        let error = Config::load(&args).unwrap_err();
+        assert!(error.to_string().starts_with("hosts[0].public_dir: "), "{error}");
+
+        fs::create_dir(dir.join("public")).unwrap();
+        let error = Config::from_file(&write(&dir, "queue.toml", TOML.replace("[timeouts]", "queue_size = 0\n\n[timeouts]")))
+            .and_then(|config| config.validate())
+            .unwrap_err();
+        assert_eq!(error.to_string(), "queue_size: must be at least 1");
+
+        let error = Args::parse(["--workers=many".to_string()]).unwrap_err();
+        assert_eq!(error.to_string(), "--workers: `many` is not a valid number");
+        let error = Args::parse(["--check-config=x".to_string()]).unwrap_err();
+        assert_eq!(error.to_string(), "--check-config: takes no value");
+
+        let twice = TOML.replace("[[hosts]]", "[[hosts]]\nnames = [\"Example.com.\"]\npublic_dir = \"public\"\n\n[[hosts]]");
+        let error = Config::from_file(&write(&dir, "twice.toml", twice)).and_then(|config| config.validate()).unwrap_err();
+        assert_eq!(error.to_string(), "hosts[1].names: `example.com` is already used by hosts[0]");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tls_errors_name_the_file_at_fault() {
+        let dir = temp_dir("config-tls");
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert = write(&dir, "cert.pem", generated.cert.pem());
+        let key = write(&dir, "key.pem", generated.signing_key.serialize_pem());
+        let garbage = write(&dir, "garbage.pem", "not pem");
+        let other = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let other_key = write(&dir, "other_key.pem", other.signing_key.serialize_pem());
+        let config = |cert: &Path, key: &Path| Config {
+            tls: Some(Tls {
+                listen: vec!["127.0.0.1:8443".to_string()],
+                cert: cert.to_path_buf(),
+                key: key.to_path_buf(),
+                redirect_http: false,
+            }),
+            ..Config::default()
+        };
+
+        assert!(config(&cert, &key).tls_config().unwrap().is_some());
+        let error = config(&garbage, &key).tls_config().unwrap_err().to_string();
+        assert!(error.starts_with("tls.cert: "), "{error}");
+        let error = config(&cert, &garbage).tls_config().unwrap_err().to_string();
+        assert!(error.starts_with("tls.key: "), "{error}");
+        let error = config(&cert, &other_key).tls_config().unwrap_err().to_string();
+        assert!(error.starts_with("tls.key: ") && error.contains("does not match the certificate"), "{error}");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+use super::http::{date, percent_encoding, Request, Response, StatusCode};
+use serde_json::{json, Value};
+use std::cmp::Ordering;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Renders the contents of `dir` as an HTML page, or as JSON when asked for
+/// with `?format=json` or an `Accept: application/json` header.
+///
+/// `?sort=name|size|modified` and `?order=asc|desc` pick the order; names
+/// ascending is the default. Directories are always listed first. Names
+/// starting with `.` are left out unless `show_hidden` is set.
+pub fn render(request: &Request, dir: &Path, show_hidden: bool) -> Response {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return Response::new(StatusCode::NotFound, None);
+    };
+
+    let mut entries: Vec<Entry> = read_dir
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') && !show_hidden {
+                return None;
+            }
+            let metadata = entry.metadata().ok()?;
+            Some(Entry {
+                name,
+                is_dir: metadata.is_dir(),
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+            })
+        })
+        .collect();
+
+    let query = request.query_string();
+    let sort = query.and_then(|q| q.get_first("sort")).unwrap_or("name");
+    let descending = query.and_then(|q| q.get_first("order")) == Some("desc");
+    entries.sort_by(|a, b| {
+        let order = match sort {
+            "size" => a.size.cmp(&b.size),
+            "modified" => a.modified.cmp(&b.modified),
+            _ => Ordering::Equal,
+        }
+        .then_with(|| a.name.cmp(&b.name));
+        let order = if descending { order.reverse() } else { order };
+        b.is_dir.cmp(&a.is_dir).then(order)
+    });
+
+    let wants_json = query.and_then(|q| q.get_first("format")) == Some("json")
+        || request
+            .header("Accept")
+            .is_some_and(|accept| accept.contains("application/json"));
+
+    if wants_json {
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(render_json(&entries))
+    } else {
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(render_html(request.path(), &entries))
+    }
+}
+
+fn render_html(path: &str, entries: &[Entry]) -> String {
+    let title = escape_html(path);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n  <head><title>Index of {title}</title></head>\n  <body>\n    <h1>Index of {title}</h1>\n    <table>\n      <tr><th><a href=\"?sort=name\">Name</a></th><th><a href=\"?sort=size\">Size</a></th><th><a href=\"?sort=modified\">Modified</a></th></tr>\n"
+    );
+    if path != "/" {
+        html.push_str("      <tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for entry in entries {
+        let suffix = if entry.is_dir { "/" } else { "" };
+        let href = percent_encoding::encode_path(&entry.name);
+        let size = if entry.is_dir { String::new() } else { entry.size.to_string() };
+        let modified = entry.modified.map(date::format).unwrap_or_default();
+        html.push_str(&format!(
+            "      <tr><td><a href=\"{href}{suffix}\">{}{suffix}</a></td><td>{size}</td><td>{modified}</td></tr>\n",
+            escape_html(&entry.name)
+        ));
+    }
+    html.push_str("    </table>\n  </body>\n</html>\n");
+    html
+}
+
+fn render_json(entries: &[Entry]) -> String {
+    let items: Vec<Value> = entries
+        .iter()
+        .map(|entry| {
+            json!({
+                "name": entry.name,
+                "type": if entry.is_dir { "directory" } else { "file" },
+                "size": entry.size,
+                "modified": entry.modified.map(date::format),
+            })
+        })
+        .collect();
+    Value::Array(items).to_string()
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
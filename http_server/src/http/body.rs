@@ -0,0 +1,115 @@
+use super::{Headers, ParseError};
+
+/// How the body of a message is delimited, decided from its headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyLength {
+    Empty,
+    Fixed(usize),
+    Chunked,
+}
+
+impl BodyLength {
+    pub fn from_headers(headers: &Headers) -> Result<Self, ParseError> {
+        let mut content_length = None;
+        for value in headers.get_all("Content-Length") {
+            // Repeated Content-Length fields are only allowed when they agree.
+            for value in value.split(',') {
+                let length = parse_content_length(value.trim())?;
+                if content_length.is_some_and(|existing| existing != length) {
+                    return Err(ParseError::InvalidHeader);
+                }
+                content_length = Some(length);
+            }
+        }
+
+        let codings: Vec<&str> = headers
+            .get_all("Transfer-Encoding")
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|coding| !coding.is_empty())
+            .collect();
+
+        if codings.is_empty() {
+            return Ok(content_length.map_or(Self::Empty, Self::Fixed));
+        }
+
+        // A message carrying both framings is a request smuggling vector,
+        // and chunked is the only transfer coding we know how to undo.
+        if content_length.is_some() || codings.len() != 1 || !codings[0].eq_ignore_ascii_case("chunked") {
+            return Err(ParseError::InvalidHeader);
+        }
+        Ok(Self::Chunked)
+    }
+}
+
+fn parse_content_length(value: &str) -> Result<usize, ParseError> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(ParseError::InvalidHeader);
+    }
+    value.parse().map_err(|_| ParseError::InvalidHeader)
+}
+
+/// Decodes a chunked body from the start of `buf`.
+///
+/// Returns the decoded bytes and the number of bytes of `buf` they took
+/// up, including the trailer section, or `None` if `buf` ends before the
+/// last chunk does. Fails with `BodyTooLarge` as soon as the decoded size
+/// passes `limit`.
+pub fn decode_chunked(buf: &[u8], limit: usize) -> Result<Option<(Vec<u8>, usize)>, ParseError> {
+    let mut body = Vec::new();
+    let mut pos = 0;
+
+    loop {
+        let Some(line) = next_line(&buf[pos..]) else {
+            return Ok(None);
+        };
+        let size_line = &buf[pos..pos + line.0];
+        pos += line.1;
+
+        // Chunk extensions after ';' carry nothing we use.
+        let size = size_line.split(|&b| b == b';').next().unwrap_or_default();
+        let size = std::str::from_utf8(size)?.trim_matches([' ', '\t']);
+        if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ParseError::InvalidChunk);
+        }
+        let size = usize::from_str_radix(size, 16).map_err(|_| ParseError::InvalidChunk)?;
+
+        if size == 0 {
+            break;
+        }
+        if body.len().saturating_add(size) > limit {
+            return Err(ParseError::BodyTooLarge);
+        }
+        if buf.len() < pos + size {
+            return Ok(None);
+        }
+        body.extend_from_slice(&buf[pos..pos + size]);
+        pos += size;
+
+        match next_line(&buf[pos..]) {
+            Some((0, skip)) => pos += skip,
+            Some(_) => return Err(ParseError::InvalidChunk),
+            None if buf.len() - pos < 2 => return Ok(None),
+            None => return Err(ParseError::InvalidChunk),
+        }
+    }
+
+    // Trailer fields are skipped up to the blank line that ends them.
+    loop {
+        let Some((len, skip)) = next_line(&buf[pos..]) else {
+            return Ok(None);
+        };
+        pos += skip;
+        if len == 0 {
+            return Ok(Some((body, pos)));
+        }
+    }
+}
+
+/// Finds the end of the first line in `buf`, returning the length of the
+/// line without its terminator and the length including it.
+fn next_line(buf: &[u8]) -> Option<(usize, usize)> {
+    let i = buf.iter().position(|&b| b == b'\n')?;
+    let len = if i > 0 && buf[i - 1] == b'\r' { i - 1 } else { i };
+    Some((len, i + 1))
+}
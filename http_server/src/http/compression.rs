@@ -0,0 +1,248 @@
+use super::{Response, ResponseBody, StatusCode};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use std::fs::File;
+use std::io::{Read, Result as IoResult, Seek, SeekFrom, Write};
+
+/// Bodies smaller than this aren't worth the CPU or the extra headers.
+pub const MIN_COMPRESS_SIZE: u64 = 1024;
+/// File bodies larger than this are streamed as they are rather than read
+/// into memory to be compressed.
+pub const MAX_COMPRESS_SIZE: u64 = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    /// Ties between equally weighted codings go to the first one here.
+    pub const PREFERENCE: [Encoding; 3] = [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate];
+
+    /// The `Content-Encoding` token.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Brotli => "br",
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> IoResult<Vec<u8>> {
+        match self {
+            Self::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+                encoder.write_all(data)?;
+                Ok(encoder.into_inner())
+            }
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Self::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Picks the coding the client weights highest among `available`, reading
+/// q-values from `Accept-Encoding`. `None` means send the body as it is.
+pub fn negotiate(accept_encoding: Option<&str>, available: &[Encoding]) -> Option<Encoding> {
+    let accept_encoding = accept_encoding?;
+    let weights: Vec<(&str, f32)> = accept_encoding
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let coding = params.next()?.trim();
+            let q = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (!coding.is_empty()).then_some((coding, q))
+        })
+        .collect();
+
+    let weight = |encoding: &Encoding| {
+        let find = |name: &str| weights.iter().find(|(coding, _)| coding.eq_ignore_ascii_case(name));
+        find(encoding.as_str())
+            .or_else(|| (*encoding == Encoding::Gzip).then(|| find("x-gzip")).flatten())
+            .or_else(|| find("*"))
+            .map_or(0.0, |(_, q)| *q)
+    };
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for encoding in available {
+        let q = weight(encoding);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((*encoding, q));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Whether a body of this type shrinks when compressed. Images, video,
+/// archives and fonts other than TTF/OTF are compressed already.
+pub fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    mime.starts_with("text/")
+        || matches!(
+            mime,
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "image/svg+xml"
+                | "image/x-icon"
+                | "font/ttf"
+                | "font/otf"
+        )
+}
+
+/// Compresses a response's body with the best coding the client accepts.
+///
+/// Leaves alone anything that isn't a full 200, already has a
+/// `Content-Encoding`, isn't a compressible type, is streamed, or is too
+/// small or too large to bother with. A HEAD answer (`head_only`) keeps a
+/// file body as it is rather than reading the file just to learn the
+/// compressed length, so it describes the uncompressed file.
+pub fn compress_response(accept_encoding: Option<&str>, mut response: Response, head_only: bool) -> Response {
+    let headers = response.headers();
+    let compressible = response.status_code() == StatusCode::OK
+        && !headers.contains("Content-Encoding")
+        && !headers.contains("Content-Range")
+        && headers.get("Content-Type").is_some_and(is_compressible)
+        && !response.body().is_some_and(ResponseBody::is_stream);
+    if !compressible {
+        return response;
+    }
+    // Caches must keep compressed and plain copies apart.
+    if !response.headers().contains_token("Vary", "Accept-Encoding") {
+        response.headers_mut().append("Vary", "Accept-Encoding");
+    }
+
+    let len = response.body().map_or(0, ResponseBody::len);
+    if !(MIN_COMPRESS_SIZE..=MAX_COMPRESS_SIZE).contains(&len) {
+        return response;
+    }
+    let Some(encoding) = negotiate(accept_encoding, &Encoding::PREFERENCE) else {
+        return response;
+    };
+    if head_only && matches!(response.body(), Some(ResponseBody::File { .. })) {
+        return response;
+    }
+
+    let Some(body) = response.take_body() else {
+        return response;
+    };
+    let data = match body {
+        ResponseBody::Bytes(bytes) => bytes,
+        ResponseBody::File { file, start, len } => match read_range(&file, start, len) {
+            Ok(data) => data,
+            Err(_) => {
+                response.set_body(ResponseBody::File { file, start, len });
+                return response;
+            }
+        },
+        parts => {
+            response.set_body(parts);
+            return response;
+        }
+    };
+
+    match encoding.compress(&data) {
+        Ok(compressed) => {
+            response.headers_mut().insert("Content-Encoding", encoding.as_str());
+            // The compressed bytes are a different representation, so a
+            // strong validator has to become a weak one.
+            if let Some(etag) = response.headers().get("ETag").filter(|etag| !etag.starts_with("W/")) {
+                let weak = format!("W/{etag}");
+                response.headers_mut().insert("ETag", weak);
+            }
+            response.set_body(compressed);
+        }
+        Err(_) => response.set_body(data),
+    }
+    response
+}
+
+fn read_range(mut file: &File, start: u64, len: u64) -> IoResult<Vec<u8>> {
+    let mut data = Vec::with_capacity(len as usize);
+    file.seek(SeekFrom::Start(start))?;
+    file.take(len).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    #[test]
+    fn negotiates_by_q_value_then_preference() {
+        let all = Encoding::PREFERENCE;
+
+        assert_eq!(negotiate(Some("gzip, deflate, br"), &all), Some(Encoding::Brotli));
+        assert_eq!(negotiate(Some("gzip;q=1.0, br;q=0.5"), &all), Some(Encoding::Gzip));
+        assert_eq!(negotiate(Some("*;q=0.2, br;q=0"), &all), Some(Encoding::Gzip));
+        assert_eq!(negotiate(Some("identity"), &all), None);
+        assert_eq!(negotiate(None, &all), None);
+    }
+
+    fn text(len: usize) -> Response {
+        Response::builder()
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .header("ETag", "\"v1\"")
+            .body("a".repeat(len))
+    }
+
+    #[test]
+    fn compresses_large_compressible_bodies() {
+        let response = compress_response(Some("gzip"), text(2048), false);
+
+        assert_eq!(response.headers().get("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.headers().get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.headers().get("ETag"), Some("W/\"v1\""));
+        assert!(response.body().unwrap().len() < 2048);
+    }
+
+    #[test]
+    fn leaves_other_responses_alone() {
+        let untouched = |response: Response| {
+            let encoding = response.headers().get("Content-Encoding").map(str::to_string);
+            let response = compress_response(Some("gzip, br"), response, false);
+            response.headers().get("Content-Encoding").map(str::to_string) == encoding
+                && response.body().unwrap().len() == 2048
+        };
+        let with = |name: &'static str, value: &'static str| {
+            let mut response = text(2048);
+            response.headers_mut().insert(name, value);
+            response
+        };
+        let partial = Response::builder()
+            .status(StatusCode::PartialContent)
+            .header("Content-Type", "text/plain")
+            .header("Content-Range", "bytes 0-2047/4096")
+            .body("a".repeat(2048));
+
+        assert!(compress_response(Some("gzip"), text(100), false).headers().get("Content-Encoding").is_none());
+        assert!(untouched(with("Content-Type", "image/png")));
+        assert!(untouched(with("Content-Encoding", "identity")));
+        assert!(untouched(partial));
+
+        let dir = temp_dir("compression");
+        let path = dir.join("big.txt");
+        std::fs::write(&path, "a".repeat(2048)).unwrap();
+        let file = Response::builder()
+            .header("Content-Type", "text/plain")
+            .body(ResponseBody::file_range(File::open(&path).unwrap(), 0, 2048));
+        let head = compress_response(Some("gzip"), file, true);
+        assert!(matches!(head.body(), Some(ResponseBody::File { .. })));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
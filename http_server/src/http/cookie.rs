@@ -0,0 +1,214 @@
+use super::{date, Headers};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Strict => "Strict",
+            Self::Lax => "Lax",
+            Self::None => "None",
+        }
+    }
+}
+
+/// A cookie to send in a `Set-Cookie` header.
+///
+/// The name and value are written as they are, so the value must not hold
+/// whitespace, quotes, commas, semicolons or backslashes; encode anything
+/// else first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    expires: Option<SystemTime>,
+    max_age: Option<Duration>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            expires: None,
+            max_age: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// A cookie that makes the browser forget the one called `name`. Path
+    /// and Domain have to match the original's for that to work.
+    pub fn removal(name: impl Into<String>) -> Self {
+        Self::new(name, "")
+            .with_max_age(Duration::ZERO)
+            .with_expires(UNIX_EPOCH)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn with_domain(mut self, domain: impl Into<String>) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    pub fn with_expires(mut self, expires: SystemTime) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    /// Takes precedence over `Expires` in browsers that understand both.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn with_http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    /// Browsers only accept `SameSite::None` on `Secure` cookies.
+    pub fn with_same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+/// The `Set-Cookie` header value.
+impl Display for Cookie {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={path}")?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={domain}")?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", date::format(expires))?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+/// The cookies a request was sent with, from its `Cookie` headers.
+#[derive(Debug, Default, Clone)]
+pub struct CookieJar<'a> {
+    cookies: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> CookieJar<'a> {
+    /// Pairs without an `=` are skipped, and surrounding double quotes are
+    /// taken off values.
+    pub fn from_headers(headers: &'a Headers<'_>) -> Self {
+        let cookies = headers
+            .get_all("Cookie")
+            .flat_map(|header| header.split(';'))
+            .filter_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                let name = name.trim();
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+                (!name.is_empty()).then_some((name, value))
+            })
+            .collect();
+        Self { cookies }
+    }
+
+    /// The value of the first cookie called `name`. Names are case-sensitive.
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.cookies.iter().find(|(n, _)| *n == name).map(|(_, value)| *value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a str)> + '_ {
+        self.cookies.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cookies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_every_attribute() {
+        let cookie = Cookie::new("id", "a3fWa")
+            .with_path("/")
+            .with_domain("example.com")
+            .with_expires(UNIX_EPOCH + Duration::from_secs(784_111_777))
+            .with_max_age(Duration::from_secs(3600))
+            .with_secure(true)
+            .with_http_only(true)
+            .with_same_site(SameSite::Lax);
+
+        assert_eq!(
+            cookie.to_string(),
+            "id=a3fWa; Path=/; Domain=example.com; Expires=Sun, 06 Nov 1994 08:49:37 GMT; Max-Age=3600; Secure; HttpOnly; SameSite=Lax"
+        );
+    }
+
+    #[test]
+    fn parses_cookie_headers() {
+        let headers = Headers::try_from("Cookie: a=1; b=\"two\"\r\nCookie: c=3;junk\r\n").unwrap();
+        let jar = CookieJar::from_headers(&headers);
+
+        assert_eq!(jar.get("a"), Some("1"));
+        assert_eq!(jar.get("b"), Some("two"));
+        assert_eq!(jar.get("c"), Some("3"));
+        assert_eq!(jar.len(), 3);
+    }
+}
@@ -0,0 +1,117 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Formats `time` as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+/// Times before 1970 are clamped to the epoch.
+pub fn format(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let days = secs / 86400;
+    let (year, month, day) = civil_from_days(days as i64);
+    let rem = secs % 86400;
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+/// Formats `time` the way Common Log Format does, e.g.
+/// `06/Nov/1994:08:49:37 +0000`. Always in UTC.
+pub fn format_clf(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = split(time);
+    format!("{day:02}/{}/{year}:{hour:02}:{minute:02}:{second:02} +0000", MONTHS[month as usize - 1])
+}
+
+/// Formats `time` as RFC 3339 in UTC, e.g. `1994-11-06T08:49:37Z`.
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = split(time);
+    format!("{year}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}Z")
+}
+
+fn split(time: SystemTime) -> (i64, u32, u32, u64, u64, u64) {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let rem = secs % 86400;
+    (year, month, day, rem / 3600, rem / 60 % 60, rem % 60)
+}
+
+/// Parses an HTTP date in the preferred `IMF-fixdate` form. The obsolete
+/// RFC 850 and asctime forms are not accepted.
+pub fn parse(s: &str) -> Option<SystemTime> {
+    let mut parts = s.split_ascii_whitespace();
+    let weekday = parts.next()?.strip_suffix(',')?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|&m| m == month)? as u32 + 1;
+    let year: i64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(|n| n.parse::<u64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if parts.next()? != "GMT" || parts.next().is_some() || time.next().is_some() {
+        return None;
+    }
+    if !DAYS.contains(&weekday) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    // The year comes from the client, so none of this may overflow.
+    let days = u64::try_from(days_from_civil(year, month, day)?).ok()?;
+    let secs = days.checked_mul(86400)?.checked_add(hour * 3600 + minute * 60 + second)?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
+// Conversions between days since the epoch and proleptic Gregorian dates,
+// after Howard Hinnant's `chrono`-compatible date algorithms.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// `None` if the year is too far out for the day count to fit an `i64`.
+fn days_from_civil(year: i64, month: u32, day: u32) -> Option<i64> {
+    let year = if month <= 2 { year.checked_sub(1)? } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era.checked_mul(146_097)?.checked_add(doe - 719_468)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_and_parses_imf_fixdate() {
+        let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+
+        assert_eq!(format(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 GMT"), Some(time));
+        assert_eq!(parse(&format(UNIX_EPOCH + Duration::from_secs(4_107_542_400))), Some(UNIX_EPOCH + Duration::from_secs(4_107_542_400)));
+        assert_eq!(parse("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse("Sun, 06 Nov 500000000000 08:49:37 GMT"), None);
+        assert_eq!(parse("Sun, 06 Nov 9223372036854775807 08:49:37 GMT"), None);
+        assert_eq!(parse("Sun, 06 Jan -9223372036854775808 08:49:37 GMT"), None);
+        assert_eq!(format_clf(time), "06/Nov/1994:08:49:37 +0000");
+        assert_eq!(format_rfc3339(time), "1994-11-06T08:49:37Z");
+    }
+}
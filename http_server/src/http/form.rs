@@ -0,0 +1,326 @@
+use super::{Headers, QueryString, StatusCode};
+use crate::server;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str;
+
+/// Caps applied while walking a `multipart/form-data` body. The whole body
+/// is in memory by then, so it is the server's `with_max_body_size` that
+/// bounds an upload first; raise both together.
+#[derive(Debug, Clone, Copy)]
+pub struct MultipartLimits {
+    pub max_parts: usize,
+    pub max_field_size: usize,
+    pub max_file_size: usize,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        Self {
+            max_parts: 100,
+            max_field_size: 64 * 1024,
+            max_file_size: server::DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+}
+
+/// Parses an `application/x-www-form-urlencoded` body. The encoding is the
+/// same as a query string's, so the result is one.
+pub fn parse_urlencoded(body: &[u8]) -> Result<QueryString<'_>, FormError> {
+    let body = str::from_utf8(body).map_err(|_| FormError::InvalidEncoding)?;
+    Ok(QueryString::from(body))
+}
+
+/// One part of a `multipart/form-data` body. Its data borrows from the
+/// request body.
+#[derive(Debug, Clone)]
+pub struct Part<'a> {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+    data: &'a [u8],
+}
+
+impl<'a> Part<'a> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The client-side file name, present only for file uploads. It is
+    /// whatever the client sent, so never use it as a path unchecked.
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// The data as text, for ordinary form fields.
+    pub fn text(&self) -> Result<&'a str, FormError> {
+        str::from_utf8(self.data).map_err(|_| FormError::InvalidEncoding)
+    }
+}
+
+/// Walks a `multipart/form-data` body one part at a time, so a handler can
+/// stop at the first part it rejects without looking at the rest. Parts
+/// borrow from the request body, which the server has already read in full;
+/// nothing is parsed from the connection as it arrives.
+#[derive(Debug)]
+pub struct Multipart<'a> {
+    body: &'a [u8],
+    delimiter: Vec<u8>,
+    limits: MultipartLimits,
+    pos: Option<usize>,
+    parts_seen: usize,
+    done: bool,
+}
+
+impl<'a> Multipart<'a> {
+    /// `content_type` is the request's `Content-Type`, which carries the
+    /// boundary.
+    pub fn new(content_type: &str, body: &'a [u8], limits: MultipartLimits) -> Result<Self, FormError> {
+        let (mime, params) = split_params(content_type);
+        if !mime.eq_ignore_ascii_case("multipart/form-data") {
+            return Err(FormError::UnsupportedContentType);
+        }
+
+        let boundary = params
+            .into_iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("boundary"))
+            .map(|(_, value)| value)
+            .filter(|boundary| !boundary.is_empty() && boundary.len() <= 70)
+            .ok_or(FormError::MissingBoundary)?;
+
+        Ok(Self {
+            body,
+            delimiter: [b"--", boundary.as_bytes()].concat(),
+            limits,
+            pos: None,
+            parts_seen: 0,
+            done: false,
+        })
+    }
+
+    fn next_part(&mut self) -> Result<Option<Part<'a>>, FormError> {
+        let body = self.body;
+
+        // The first delimiter may follow a preamble, which is ignored.
+        let mut pos = match self.pos {
+            Some(pos) => pos,
+            None => find(body, &self.delimiter).ok_or(FormError::Malformed)? + self.delimiter.len(),
+        };
+
+        if body[pos..].starts_with(b"--") {
+            return Ok(None);
+        }
+        while matches!(body.get(pos), Some(b' ' | b'\t')) {
+            pos += 1;
+        }
+        if !body[pos..].starts_with(b"\r\n") {
+            return Err(FormError::Malformed);
+        }
+
+        let head_end = pos + find(&body[pos..], b"\r\n\r\n").ok_or(FormError::Malformed)?;
+        let head = str::from_utf8(&body[(pos + 2).min(head_end)..head_end])
+            .map_err(|_| FormError::InvalidEncoding)?;
+        let headers = Headers::try_from(head).map_err(|_| FormError::Malformed)?;
+
+        let data_start = head_end + 4;
+        let closing = [b"\r\n", self.delimiter.as_slice()].concat();
+        let data_end = data_start + find(&body[data_start..], &closing).ok_or(FormError::Malformed)?;
+        self.pos = Some(data_end + closing.len());
+
+        self.parts_seen += 1;
+        if self.parts_seen > self.limits.max_parts {
+            return Err(FormError::TooManyParts);
+        }
+
+        let disposition = headers.get("Content-Disposition").ok_or(FormError::Malformed)?;
+        let (kind, params) = split_params(disposition);
+        if !kind.eq_ignore_ascii_case("form-data") {
+            return Err(FormError::Malformed);
+        }
+        let mut name = None;
+        let mut filename = None;
+        for (key, value) in params {
+            if key.eq_ignore_ascii_case("name") {
+                name = Some(value);
+            } else if key.eq_ignore_ascii_case("filename") {
+                filename = Some(value);
+            }
+        }
+
+        let data = &body[data_start..data_end];
+        let limit = match filename {
+            Some(_) => self.limits.max_file_size,
+            None => self.limits.max_field_size,
+        };
+        if data.len() > limit {
+            return Err(FormError::PartTooLarge);
+        }
+
+        Ok(Some(Part {
+            name: name.ok_or(FormError::Malformed)?,
+            filename,
+            content_type: headers.get("Content-Type").map(str::to_string),
+            data,
+        }))
+    }
+}
+
+impl<'a> Iterator for Multipart<'a> {
+    type Item = Result<Part<'a>, FormError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let part = self.next_part().transpose();
+        // Stop after the closing delimiter and after the first error.
+        if !matches!(part, Some(Ok(_))) {
+            self.done = true;
+        }
+        part
+    }
+}
+
+/// Splits a header value such as `form-data; name="file"; filename="a.txt"`
+/// into its leading token and its parameters, with quotes removed.
+pub fn split_params(value: &str) -> (&str, Vec<(&str, String)>) {
+    let (head, mut rest) = value.split_once(';').unwrap_or((value, ""));
+    let mut params = Vec::new();
+
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ';']);
+        let Some(eq) = rest.find('=') else {
+            break;
+        };
+        let key = rest[..eq].trim();
+        rest = &rest[eq + 1..];
+
+        let mut param = String::new();
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => param.extend(chars.next().map(|(_, c)| c)),
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    c => param.push(c),
+                }
+            }
+            rest = &quoted[end..];
+        } else {
+            let end = rest.find(';').unwrap_or(rest.len());
+            param.push_str(rest[..end].trim());
+            rest = &rest[end..];
+        }
+        params.push((key, param));
+    }
+
+    (head.trim(), params)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[derive(Debug)]
+pub enum FormError {
+    UnsupportedContentType,
+    InvalidEncoding,
+    MissingBoundary,
+    Malformed,
+    TooManyParts,
+    PartTooLarge,
+}
+
+impl FormError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::UnsupportedContentType => StatusCode::UnsupportedMediaType,
+            Self::PartTooLarge => StatusCode::PayloadTooLarge,
+            _ => StatusCode::BadRequest,
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            Self::UnsupportedContentType => "Unsupported Content Type",
+            Self::InvalidEncoding => "Invalid Encoding",
+            Self::MissingBoundary => "Missing Boundary",
+            Self::Malformed => "Malformed Form",
+            Self::TooManyParts => "Too Many Parts",
+            Self::PartTooLarge => "Part Too Large",
+        }
+    }
+}
+
+impl Display for FormError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl Error for FormError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = b"preamble\r\n--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nHello\r\n--XyZ\r\nContent-Disposition: form-data; name=\"upload\"; filename=\"a \\\"b\\\".png\"\r\nContent-Type: image/png\r\n\r\n\x89PNG\r\n--XyZ--\r\n";
+
+    #[test]
+    fn walks_fields_and_files() {
+        let parts: Vec<Part> = Multipart::new("multipart/form-data; boundary=\"XyZ\"", BODY, MultipartLimits::default())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name(), "title");
+        assert_eq!(parts[0].text().unwrap(), "Hello");
+        assert!(!parts[0].is_file());
+        assert_eq!(parts[1].filename(), Some("a \"b\".png"));
+        assert_eq!(parts[1].content_type(), Some("image/png"));
+        assert_eq!(parts[1].data(), b"\x89PNG");
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let limits = MultipartLimits {
+            max_file_size: 2,
+            ..MultipartLimits::default()
+        };
+        let mut parts = Multipart::new("multipart/form-data; boundary=XyZ", BODY, limits).unwrap();
+
+        assert!(parts.next().unwrap().is_ok());
+        assert!(matches!(parts.next(), Some(Err(FormError::PartTooLarge))));
+        assert!(parts.next().is_none());
+    }
+
+    #[test]
+    fn parses_urlencoded_bodies() {
+        let form = parse_urlencoded(b"name=Jane+Doe&lang=rust&lang=c").unwrap();
+
+        assert_eq!(form.get_first("name"), Some("Jane Doe"));
+        assert_eq!(form.get_all("lang").count(), 2);
+    }
+}
@@ -0,0 +1,131 @@
+use super::ParseError;
+use std::borrow::Cow;
+use std::convert::TryFrom;
+
+/// An ordered, case-insensitive multimap of HTTP header fields.
+///
+/// Names keep the case they were written with; lookups ignore it.
+/// Repeated fields are kept as separate entries, in the order they arrived.
+#[derive(Debug, Default, Clone)]
+pub struct Headers<'buf> {
+    data: Vec<(Cow<'buf, str>, Cow<'buf, str>)>,
+}
+
+impl<'buf> Headers<'buf> {
+    pub fn new() -> Self {
+        Self { data: Vec::new() }
+    }
+
+    /// The first value of `name`, if present.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.data
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_ref())
+    }
+
+    /// Every value of `name`, in the order they were added.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.data
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_ref())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Whether any value of a comma-separated header such as `Connection`
+    /// lists `token`.
+    pub fn contains_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.data.iter().map(|(key, value)| (key.as_ref(), value.as_ref()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Adds a value, keeping any existing values for the same name.
+    pub fn append(&mut self, name: impl Into<Cow<'buf, str>>, value: impl Into<Cow<'buf, str>>) {
+        self.data.push((name.into(), value.into()));
+    }
+
+    /// Sets a value, replacing any existing values for the same name.
+    pub fn insert(&mut self, name: impl Into<Cow<'buf, str>>, value: impl Into<Cow<'buf, str>>) {
+        let name = name.into();
+        self.remove(&name);
+        self.data.push((name, value.into()));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.data.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+}
+
+/// Parses a header block: the lines after the request line, up to the
+/// blank line. Folded continuation lines are joined onto the previous
+/// value with a single space.
+impl<'buf> TryFrom<&'buf str> for Headers<'buf> {
+    type Error = ParseError;
+
+    fn try_from(s: &'buf str) -> Result<Self, Self::Error> {
+        let mut headers = Headers::new();
+
+        for line in s.split('\n') {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if line.is_empty() {
+                break;
+            }
+
+            if line.starts_with([' ', '\t']) {
+                let (_, value) = headers.data.last_mut().ok_or(ParseError::InvalidHeader)?;
+                let continuation = line.trim_matches([' ', '\t']);
+                if !is_valid_value(continuation) {
+                    return Err(ParseError::InvalidHeader);
+                }
+                if !continuation.is_empty() {
+                    let value = value.to_mut();
+                    if !value.is_empty() {
+                        value.push(' ');
+                    }
+                    value.push_str(continuation);
+                }
+                continue;
+            }
+
+            let i = line.find(':').ok_or(ParseError::InvalidHeader)?;
+            let name = &line[..i];
+            let value = line[i + 1..].trim_matches([' ', '\t']);
+
+            if !is_valid_name(name) || !is_valid_value(value) {
+                return Err(ParseError::InvalidHeader);
+            }
+
+            headers.append(name, value);
+        }
+
+        Ok(headers)
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.bytes().all(|b| {
+            b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+        })
+}
+
+fn is_valid_value(value: &str) -> bool {
+    value.bytes().all(|b| b == b'\t' || (b >= b' ' && b != 0x7f))
+}
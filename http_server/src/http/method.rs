@@ -1,5 +1,6 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::str::FromStr;
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Method {
     GET,
     DELETE,
@@ -30,4 +31,26 @@ impl FromStr for Method {
     }
 }
 
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::GET => "GET",
+            Self::DELETE => "DELETE",
+            Self::POST => "POST",
+            Self::PUT => "PUT",
+            Self::HEAD => "HEAD",
+            Self::CONNECT => "CONNECT",
+            Self::OPTIONS => "OPTIONS",
+            Self::TRACE => "TRACE",
+            Self::PATCH => "PATCH",
+        }
+    }
+}
+
+impl Display for Method {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.as_str())
+    }
+}
+
 pub struct MethodError;
@@ -1,12 +1,23 @@
 pub use request::Request;
+pub use cookie::{Cookie, CookieJar, SameSite};
+pub use headers::Headers;
 pub use method::Method;
 pub use request::ParseError;
 pub use query_string::{QueryString, Value as QueryStringValue};
-pub use response::Response;
+pub use response::{BodyStream, Response, ResponseBody, ResponseBuilder, Transport, Upgrade};
 pub use status_code::StatusCode;
 
 pub mod method;
+pub mod compression;
+pub mod cookie;
+pub mod date;
+pub mod form;
+pub mod mime;
+pub mod body;
+pub mod headers;
 pub mod request;
+pub mod percent_encoding;
 pub mod query_string;
+pub mod range;
 pub mod response;
 pub mod status_code;
@@ -0,0 +1,61 @@
+use std::borrow::Cow;
+
+/// Decodes `%XX` escapes, and `+` as a space when `plus_as_space` is set
+/// (as in query strings and form bodies).
+///
+/// Borrows the input when there is nothing to decode. Malformed escapes
+/// are kept as written, and bytes that don't decode to UTF-8 become U+FFFD.
+pub fn decode(s: &str, plus_as_space: bool) -> Cow<'_, str> {
+    let has_plus = plus_as_space && s.contains('+');
+    if !s.contains('%') && !has_plus {
+        return Cow::Borrowed(s);
+    }
+
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => match (bytes.get(i + 1).and_then(hex_value), bytes.get(i + 2).and_then(hex_value)) {
+                (Some(high), Some(low)) => {
+                    decoded.push(high << 4 | low);
+                    i += 3;
+                    continue;
+                }
+                _ => decoded.push(b'%'),
+            },
+            b'+' if plus_as_space => decoded.push(b' '),
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+
+    match String::from_utf8(decoded) {
+        Ok(decoded) => Cow::Owned(decoded),
+        Err(e) => Cow::Owned(String::from_utf8_lossy(e.as_bytes()).into_owned()),
+    }
+}
+
+/// Percent-encodes everything in `s` except unreserved characters and `/`,
+/// so a decoded path can be put back into a URL.
+pub fn encode_path(s: &str) -> Cow<'_, str> {
+    let keep = |b: u8| b.is_ascii_alphanumeric() || b"-._~/".contains(&b);
+    if s.bytes().all(keep) {
+        return Cow::Borrowed(s);
+    }
+
+    let mut encoded = String::with_capacity(s.len() * 3);
+    for b in s.bytes() {
+        if keep(b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{b:02X}"));
+        }
+    }
+    Cow::Owned(encoded)
+}
+
+fn hex_value(b: &u8) -> Option<u8> {
+    (*b as char).to_digit(16).map(|d| d as u8)
+}
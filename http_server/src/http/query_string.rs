@@ -1,21 +1,56 @@
+use super::percent_encoding;
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::str::FromStr;
 
 
-#[derive(Debug)]
+/// Keys and values are percent-decoded, borrowing from the request buffer
+/// unless decoding changed them.
+#[derive(Debug, Clone)]
 pub struct QueryString<'buf> {
-    data: HashMap<&'buf str, Value<'buf>>,
+    data: HashMap<Cow<'buf, str>, Value<'buf>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Value<'buf> {
-    Single(&'buf str),
-    Multiple(Vec<&'buf str>),
+    Single(Cow<'buf, str>),
+    Multiple(Vec<Cow<'buf, str>>),
+}
+
+impl<'buf> Value<'buf> {
+    pub fn as_slice(&self) -> &[Cow<'buf, str>] {
+        match self {
+            Value::Single(val) => std::slice::from_ref(val),
+            Value::Multiple(vec) => vec,
+        }
+    }
 }
 
 impl <'buf> QueryString<'buf> {
-    pub fn get(&self, key: &str) -> Option<&Value> {
+    pub fn get(&self, key: &str) -> Option<&Value<'_>> {
         self.data.get(key)
     }
+
+    /// The first value given for `key`.
+    pub fn get_first(&self, key: &str) -> Option<&str> {
+        self.get_all(key).next()
+    }
+
+    /// Every value given for `key`, in the order they appeared.
+    pub fn get_all(&self, key: &str) -> impl Iterator<Item = &str> {
+        self.data
+            .get(key)
+            .map_or(&[][..], Value::as_slice)
+            .iter()
+            .map(|val| val.as_ref())
+    }
+
+    /// The first value for `key` parsed into `T`, e.g.
+    /// `query.get_as::<u32>("page")`. `None` if it is missing or doesn't
+    /// parse.
+    pub fn get_as<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.get_first(key)?.parse().ok()
+    }
 }
 
 impl<'buf> From<&'buf str> for QueryString<'buf> {
@@ -31,6 +66,9 @@ impl<'buf> From<&'buf str> for QueryString<'buf> {
                 val = &sub_str[i+1..];
             }
 
+            let key = percent_encoding::decode(key, true);
+            let val = percent_encoding::decode(val, true);
+
             data.entry(key)
                 .and_modify(|existing: &mut Value|match existing {
                     Value::Single(prev_val) => {
@@ -39,11 +77,11 @@ impl<'buf> From<&'buf str> for QueryString<'buf> {
                         //vec.push(pre_val);
 
                         //let mut vec = vec![prev_val, val];
-                        *existing = Value::Multiple(vec!(prev_val, val));
+                        *existing = Value::Multiple(vec!(prev_val.clone(), val.clone()));
                         
 
                     },
-                    Value::Multiple(vec) => vec.push(val),
+                    Value::Multiple(vec) => vec.push(val.clone()),
                 })
                 .or_insert(Value::Single(val));
 
@@ -54,3 +92,27 @@ impl<'buf> From<&'buf str> for QueryString<'buf> {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_keys_and_values() {
+        let query = QueryString::from("q=caf%C3%A9+au+lait&tag%5B%5D=a&plain=x&bad=%zz");
+
+        assert_eq!(query.get_first("q"), Some("café au lait"));
+        assert_eq!(query.get_first("tag[]"), Some("a"));
+        assert_eq!(query.get_first("bad"), Some("%zz"));
+        assert!(matches!(query.get("plain"), Some(Value::Single(Cow::Borrowed("x")))));
+    }
+
+    #[test]
+    fn collects_repeated_keys_and_parses_values() {
+        let query = QueryString::from("page=3&id=1&id=2&id=oops");
+
+        assert_eq!(query.get_all("id").collect::<Vec<_>>(), ["1", "2", "oops"]);
+        assert_eq!(query.get_as::<u32>("page"), Some(3));
+        assert_eq!(query.get_as::<u32>("missing"), None);
+        assert_eq!(query.get_all("missing").count(), 0);
+    }
+}
@@ -0,0 +1,120 @@
+/// An inclusive span of bytes, as written in a `Range` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        match self.is_empty() {
+            true => 0,
+            false => self.end - self.start + 1,
+        }
+    }
+
+    /// Only a range built by hand with `end` before `start` is empty;
+    /// `parse` never returns one.
+    pub fn is_empty(&self) -> bool {
+        self.end < self.start
+    }
+
+    /// The `Content-Range` value for this span of a `total`-byte resource.
+    pub fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total)
+    }
+}
+
+/// How to answer a request given its `Range` header.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// Send the whole resource, because the header was absent, malformed,
+    /// or not worth honouring.
+    Full,
+    Partial(Vec<ByteRange>),
+    /// None of the ranges overlap the resource: answer 416.
+    Unsatisfiable,
+}
+
+/// More ranges than this in one request are ignored and the whole resource
+/// is sent instead, so a client can't make us do lots of tiny seeks.
+const MAX_RANGES: usize = 16;
+
+/// Interprets a `Range` header against a resource of `len` bytes.
+pub fn parse(value: &str, len: u64) -> RangeRequest {
+    let Some(specs) = value.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+
+    let mut ranges = Vec::new();
+    let mut count = 0;
+    for spec in specs.split(',').map(str::trim).filter(|spec| !spec.is_empty()) {
+        count += 1;
+        let Some((first, last)) = spec.split_once('-') else {
+            return RangeRequest::Full;
+        };
+        let (first, last) = (first.trim(), last.trim());
+
+        let range = if first.is_empty() {
+            // `-N` asks for the final N bytes.
+            let Ok(suffix) = last.parse::<u64>() else {
+                return RangeRequest::Full;
+            };
+            (suffix > 0 && len > 0).then(|| ByteRange {
+                start: len.saturating_sub(suffix),
+                end: len - 1,
+            })
+        } else {
+            let Ok(start) = first.parse::<u64>() else {
+                return RangeRequest::Full;
+            };
+            let end = match last {
+                "" => u64::MAX,
+                last => match last.parse::<u64>() {
+                    Ok(end) if end >= start => end,
+                    _ => return RangeRequest::Full,
+                },
+            };
+            (start < len).then(|| ByteRange {
+                start,
+                end: end.min(len - 1),
+            })
+        };
+        ranges.extend(range);
+    }
+
+    if count == 0 || count > MAX_RANGES {
+        RangeRequest::Full
+    } else if ranges.is_empty() {
+        RangeRequest::Unsatisfiable
+    } else {
+        RangeRequest::Partial(ranges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_byte_range_forms() {
+        assert_eq!(parse("bytes=0-99", 1000), RangeRequest::Partial(vec![ByteRange { start: 0, end: 99 }]));
+        assert_eq!(parse("bytes=900-", 1000), RangeRequest::Partial(vec![ByteRange { start: 900, end: 999 }]));
+        assert_eq!(parse("bytes=-100", 50), RangeRequest::Partial(vec![ByteRange { start: 0, end: 49 }]));
+        assert_eq!(
+            parse("bytes=0-0, 990-2000", 1000),
+            RangeRequest::Partial(vec![ByteRange { start: 0, end: 0 }, ByteRange { start: 990, end: 999 }])
+        );
+        assert_eq!(ByteRange { start: 5, end: 5 }.len(), 1);
+        assert!(ByteRange { start: 5, end: 4 }.is_empty());
+        assert_eq!(ByteRange { start: 5, end: 4 }.len(), 0);
+    }
+
+    #[test]
+    fn falls_back_or_refuses() {
+        assert_eq!(parse("items=0-1", 1000), RangeRequest::Full);
+        assert_eq!(parse("bytes=5-1", 1000), RangeRequest::Full);
+        assert_eq!(parse("bytes=1000-", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse("bytes=-0", 1000), RangeRequest::Unsatisfiable);
+    }
+}
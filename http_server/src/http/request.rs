@@ -4,14 +4,27 @@ use std::convert::TryFrom;
 use std::error::Error;
 use std::fmt::{Result as FmtResult, Display,Debug,Formatter};
 use std::str;
-use super::{QueryString, QueryStringValue};
-#[derive(Debug)]
+use std::str::FromStr;
+use std::borrow::Cow;
+use std::net::IpAddr;
+use super::body::{self, BodyLength};
+use super::form::{self, FormError, Multipart, MultipartLimits};
+use super::percent_encoding;
+use super::{CookieJar, Headers, QueryString, QueryStringValue, StatusCode};
+#[derive(Debug, Clone)]
 pub struct Request<'buf> {
-    path: &'buf str,
+    target: &'buf str,
+    path: Cow<'buf, str>,
     //query_string: Option<&'buff str>,
     query_string: Option<QueryString<'buf>>,
     //method: super::method::Method,
     method: Method,
+    headers: Headers<'buf>,
+    body: Cow<'buf, [u8]>,
+    params: Vec<(String, String)>,
+    user: Option<String>,
+    remote_addr: Option<IpAddr>,
+    secure: bool,
 }
 
 impl<'buf> Request<'buf> {
@@ -22,17 +35,173 @@ impl<'buf> Request<'buf> {
     }
     */
 
+    /// The request-target exactly as sent, query string included.
+    pub fn target(&self) -> &str {
+        self.target
+    }
+
+    /// The percent-decoded path, without the query string.
     pub fn path(&self) -> &str {
         &self.path
     }
 
+    /// Replaces the path, e.g. with a normalised form of it. The target is
+    /// left as it was sent.
+    pub fn with_path(mut self, path: String) -> Self {
+        self.path = Cow::Owned(path);
+        self
+    }
+
     pub fn method(&self) -> &Method {
         &self.method
     }
 
-    pub fn query_string(&self) -> Option<&QueryString> {
+    pub fn query_string(&self) -> Option<&QueryString<'_>> {
         self.query_string.as_ref()
     }
+
+    /// The first value of the header `name`, matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    pub fn headers(&self) -> &Headers<'buf> {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut Headers<'buf> {
+        &mut self.headers
+    }
+
+    /// The cookies sent with the request.
+    pub fn cookies(&self) -> CookieJar<'_> {
+        CookieJar::from_headers(&self.headers)
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.cookies().get(name)
+    }
+
+    /// A path parameter captured by the `Router`, such as `id` in
+    /// `/users/:id`.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// A path parameter parsed into `T`; `None` if it is missing or
+    /// doesn't parse.
+    pub fn param_as<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.param(name)?.parse().ok()
+    }
+
+    pub fn params(&self) -> &[(String, String)] {
+        &self.params
+    }
+
+    /// Who the request was authenticated as, if it went through `Auth`.
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    pub fn with_user(mut self, user: String) -> Self {
+        self.user = Some(user);
+        self
+    }
+
+    /// The address of the client that sent the request, if the `Server`
+    /// knows it.
+    pub fn remote_addr(&self) -> Option<IpAddr> {
+        self.remote_addr
+    }
+
+    pub fn with_remote_addr(mut self, remote_addr: Option<IpAddr>) -> Self {
+        self.remote_addr = remote_addr;
+        self
+    }
+
+    /// Whether the request arrived over TLS.
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Attaches the path parameters captured while routing.
+    pub fn with_params(mut self, params: Vec<(String, String)>) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// Whether the client is willing to send further requests on this
+    /// connection. HTTP/1.1 keeps connections open unless told otherwise.
+    pub fn keep_alive(&self) -> bool {
+        !self.headers.contains_token("Connection", "close")
+    }
+
+    /// The message body, with any chunked transfer coding removed.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Length of the request line and header block, including the blank line
+    /// that ends them.
+    pub fn head_len(buf: &[u8]) -> Option<usize> {
+        buf.iter()
+            .enumerate()
+            .filter(|&(_, &b)| b == b'\n')
+            .find_map(|(i, _)| {
+                let rest = &buf[i + 1..];
+                if rest.starts_with(b"\r\n") {
+                    Some(i + 3)
+                } else if rest.starts_with(b"\n") {
+                    Some(i + 2)
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// The fields of an `application/x-www-form-urlencoded` body.
+    pub fn form(&self) -> Result<QueryString<'_>, FormError> {
+        let content_type = self.header("Content-Type").unwrap_or_default();
+        let (mime, _) = form::split_params(content_type);
+        if !mime.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+            return Err(FormError::UnsupportedContentType);
+        }
+        form::parse_urlencoded(&self.body)
+    }
+
+    /// The parts of a `multipart/form-data` body, produced one at a time
+    /// from the buffered body.
+    pub fn multipart(&self, limits: MultipartLimits) -> Result<Multipart<'_>, FormError> {
+        let content_type = self.header("Content-Type").unwrap_or_default();
+        Multipart::new(content_type, &self.body, limits)
+    }
+
+    /// Works out how many bytes at the start of `buf` make up one complete
+    /// request, or `None` if more need to be read first.
+    pub fn message_len(buf: &[u8], max_body_size: usize) -> Result<Option<usize>, ParseError> {
+        let Some(head_len) = Self::head_len(buf) else {
+            return Ok(None);
+        };
+        let head = str::from_utf8(&buf[..head_len])?;
+        let fields = head.split_once('\n').map_or("", |(_, fields)| fields);
+        let headers = Headers::try_from(fields)?;
+
+        match BodyLength::from_headers(&headers)? {
+            BodyLength::Empty => Ok(Some(head_len)),
+            BodyLength::Fixed(len) if len > max_body_size => Err(ParseError::BodyTooLarge),
+            BodyLength::Fixed(len) => Ok((buf.len() >= head_len + len).then_some(head_len + len)),
+            BodyLength::Chunked => Ok(body::decode_chunked(&buf[head_len..], max_body_size)?
+                .map(|(_, used)| head_len + used)),
+        }
+    }
 }
 
 impl<'buf>TryFrom<&'buf [u8]> for Request<'buf> {
@@ -53,7 +222,8 @@ impl<'buf>TryFrom<&'buf [u8]> for Request<'buf> {
         }
         */
 
-        let request = str::from_utf8(buf)?;
+        let (head, rest) = buf.split_at(Request::head_len(buf).unwrap_or(buf.len()));
+        let request = str::from_utf8(head)?;
 
         /*
         match get_next_word(request) {
@@ -64,13 +234,37 @@ impl<'buf>TryFrom<&'buf [u8]> for Request<'buf> {
         */
 
         let (method, request) = get_next_word(request).ok_or(ParseError::InvalidRequest)?;
-        let (mut path, request) = get_next_word(request).ok_or(ParseError::InvalidRequest)?;
-        let (protocol, _) = get_next_word(request).ok_or(ParseError::InvalidRequest)?;
+        let (target, request) = get_next_word(request).ok_or(ParseError::InvalidRequest)?;
+        let mut path = target;
+        let (protocol, request) = get_next_word(request).ok_or(ParseError::InvalidRequest)?;
     
         if protocol != "HTTP/1.1" {
             return Err(ParseError::InvalidProtocol)
         }
 
+        // The request line must end right after the protocol.
+        let request = match request {
+            "" => "",
+            _ => request
+                .strip_prefix("\r\n")
+                .or_else(|| request.strip_prefix('\n'))
+                .ok_or(ParseError::InvalidRequest)?,
+        };
+        let headers = Headers::try_from(request)?;
+
+        if headers.get_all("Host").count() > 1 {
+            return Err(ParseError::DuplicateHeader);
+        }
+
+        let body = match BodyLength::from_headers(&headers)? {
+            BodyLength::Empty => Cow::Borrowed(&[][..]),
+            BodyLength::Fixed(len) => Cow::Borrowed(rest.get(..len).ok_or(ParseError::InvalidRequest)?),
+            BodyLength::Chunked => match body::decode_chunked(rest, usize::MAX)? {
+                Some((body, _)) => Cow::Owned(body),
+                None => return Err(ParseError::InvalidRequest),
+            },
+        };
+
         let method: Method  = method.parse()?;
         let mut query_string = None;
         /*
@@ -96,9 +290,16 @@ impl<'buf>TryFrom<&'buf [u8]> for Request<'buf> {
         }
 
         Ok(Self {
-            path,
+            target,
+            path: percent_encoding::decode(path, false),
             query_string,
             method,
+            headers,
+            body,
+            params: Vec::new(),
+            user: None,
+            remote_addr: None,
+            secure: false,
         })
 
 
@@ -126,11 +327,17 @@ fn get_next_word(request: &str) -> Option<(&str, &str)> {
     }
 }
 
+#[derive(Debug)]
 pub enum ParseError {
     InvalidRequest,
     InvalidEncoding,
     InvalidProtocol,
     InvalidMethod,
+    InvalidHeader,
+    DuplicateHeader,
+    InvalidChunk,
+    BodyTooLarge,
+    HeadersTooLarge,
 }
 
 impl Display for ParseError {
@@ -160,12 +367,95 @@ impl From<Utf8Error> for ParseError {
 }
 
 impl ParseError {
+    /// The status to answer with when a request fails to parse.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::BodyTooLarge => StatusCode::PayloadTooLarge,
+            Self::HeadersTooLarge => StatusCode::RequestHeaderFieldsTooLarge,
+            Self::InvalidMethod => StatusCode::NotImplemented,
+            _ => StatusCode::BadRequest,
+        }
+    }
+
     fn message(&self) -> String {
         match self {
             Self::InvalidRequest => "Invalid Request".to_string(),
             Self::InvalidEncoding => "Invalid Encoding".to_string(),
             Self::InvalidProtocol => "Invalid Protocol".to_string(),
             Self::InvalidMethod => "Invalid Method".to_string(),
+            Self::InvalidHeader => "Invalid Header".to_string(),
+            Self::DuplicateHeader => "Duplicate Header".to_string(),
+            Self::InvalidChunk => "Invalid Chunk".to_string(),
+            Self::BodyTooLarge => "Body Too Large".to_string(),
+            Self::HeadersTooLarge => "Headers Too Large".to_string(),
         }
     } 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_headers_case_insensitively() {
+        let buf = b"GET /index.html HTTP/1.1\r\nHost: localhost\r\nContent-Type: text/html\r\n\r\n";
+        let request = Request::try_from(&buf[..]).unwrap();
+
+        assert_eq!(request.path(), "/index.html");
+        assert_eq!(request.header("host"), Some("localhost"));
+        assert_eq!(request.header("CONTENT-TYPE"), Some("text/html"));
+        assert_eq!(request.header("Cookie"), None);
+    }
+
+    #[test]
+    fn keeps_duplicate_and_folded_headers() {
+        let buf = b"GET / HTTP/1.1\r\nAccept: text/html\r\nX-Long: one\r\n  two\r\naccept: */*\r\n\r\n";
+        let request = Request::try_from(&buf[..]).unwrap();
+
+        let accept: Vec<_> = request.headers().get_all("Accept").collect();
+        assert_eq!(accept, ["text/html", "*/*"]);
+        assert_eq!(request.header("X-Long"), Some("one two"));
+    }
+
+    #[test]
+    fn rejects_malformed_header_lines() {
+        let missing_colon = b"GET / HTTP/1.1\r\nHost localhost\r\n\r\n";
+        let space_in_name = b"GET / HTTP/1.1\r\nHost : localhost\r\n\r\n";
+        let leading_fold = b"GET / HTTP/1.1\r\n folded\r\n\r\n";
+        let two_hosts = b"GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n";
+
+        assert!(matches!(Request::try_from(&missing_colon[..]), Err(ParseError::InvalidHeader)));
+        assert!(matches!(Request::try_from(&space_in_name[..]), Err(ParseError::InvalidHeader)));
+        assert!(matches!(Request::try_from(&leading_fold[..]), Err(ParseError::InvalidHeader)));
+        assert!(matches!(Request::try_from(&two_hosts[..]), Err(ParseError::DuplicateHeader)));
+    }
+
+    #[test]
+    fn reads_content_length_and_chunked_bodies() {
+        let fixed = b"POST /form HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+        let chunked = b"POST /form HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\nX-Trailer: 1\r\n\r\n";
+
+        assert_eq!(Request::try_from(&fixed[..]).unwrap().body(), b"hello");
+        assert_eq!(Request::try_from(&chunked[..]).unwrap().body(), b"hello world");
+        assert_eq!(Request::message_len(&chunked[..], 1024).unwrap(), Some(chunked.len()));
+    }
+
+    #[test]
+    fn message_len_waits_for_the_whole_body() {
+        let partial = b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nabc";
+        let partial_chunk = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel";
+
+        assert_eq!(Request::message_len(&partial[..], 1024).unwrap(), None);
+        assert_eq!(Request::message_len(&partial_chunk[..], 1024).unwrap(), None);
+        assert!(matches!(Request::message_len(&partial[..], 4), Err(ParseError::BodyTooLarge)));
+    }
+
+    #[test]
+    fn decodes_path_but_not_plus() {
+        let buf = b"GET /my%20docs/a+b.txt?q=a+b HTTP/1.1\r\n\r\n";
+        let request = Request::try_from(&buf[..]).unwrap();
+
+        assert_eq!(request.path(), "/my docs/a+b.txt");
+        assert_eq!(request.query_string().unwrap().get_first("q"), Some("a b"));
+    }
+}
@@ -1,31 +1,415 @@
-use super::StatusCode;
+use super::{Cookie, Headers, StatusCode};
+use std::borrow::Cow;
 use std::net::TcpStream;
-use std::io::{Write, Result as IoResult};
-use std::fmt::{Display,Formatter, Result as FmtResult};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write, Result as IoResult};
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use std::sync::Mutex;
 
 #[derive(Debug)]
 pub struct Response {
     status_code: StatusCode,
-    body: Option<String>,
+    headers: Headers<'static>,
+    body: Option<ResponseBody>,
+    upgrade: Option<Upgrade>,
+}
+
+/// A connection responses are written to: plain TCP, or TLS over it.
+pub trait Transport: Read + Write {
+    /// The socket underneath, for setting timeouts.
+    fn socket(&self) -> &TcpStream;
+}
+
+impl Transport for TcpStream {
+    fn socket(&self) -> &TcpStream {
+        self
+    }
+}
+
+/// Takes over the connection once a `101 Switching Protocols` response has
+/// been sent, speaking whatever protocol was switched to until it returns.
+/// The connection is closed afterwards.
+pub struct Upgrade(Box<UpgradeFn>);
+
+type UpgradeFn = dyn FnOnce(&mut dyn Transport) + Send;
+
+impl Upgrade {
+    pub fn new(f: impl FnOnce(&mut dyn Transport) + Send + 'static) -> Self {
+        Self(Box::new(f))
+    }
+
+    pub fn run(self, stream: &mut dyn Transport) {
+        (self.0)(stream)
+    }
+}
+
+impl Debug for Upgrade {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str("Upgrade")
+    }
+}
+
+/// What a response sends after its headers. File contents are copied to
+/// the socket when the response is sent rather than read up front.
+#[derive(Debug)]
+pub enum ResponseBody {
+    Bytes(Vec<u8>),
+    File { file: File, start: u64, len: u64 },
+    /// Several bodies sent back to back, e.g. the parts of a
+    /// `multipart/byteranges` response.
+    Parts(Vec<ResponseBody>),
+    /// A body produced while it is sent, for as long as it takes.
+    Stream(BodyStream),
+}
+
+/// Writes a body of unknown length straight to the connection after the
+/// headers have gone out. The server runs it and then closes the
+/// connection, which is how the client learns the body is over, so
+/// `Response::send` alone writes nothing for it.
+pub struct BodyStream(Mutex<Option<Box<StreamFn>>>);
+
+type StreamFn = dyn FnOnce(&mut dyn Transport) -> IoResult<()> + Send;
+
+impl BodyStream {
+    pub fn new(f: impl FnOnce(&mut dyn Transport) -> IoResult<()> + Send + 'static) -> Self {
+        Self(Mutex::new(Some(Box::new(f))))
+    }
+
+    /// Writes the body. Does nothing if it has already been written.
+    pub fn run(&self, stream: &mut dyn Transport) -> IoResult<()> {
+        let f = self.0.lock().unwrap_or_else(|e| e.into_inner()).take();
+        match f {
+            Some(f) => f(stream),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Debug for BodyStream {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str("BodyStream")
+    }
+}
+
+impl ResponseBody {
+    /// `len` bytes of `file`, starting `start` bytes in.
+    pub fn file_range(file: File, start: u64, len: u64) -> Self {
+        Self::File { file, start, len }
+    }
+
+    pub fn len(&self) -> u64 {
+        match self {
+            Self::Bytes(bytes) => bytes.len() as u64,
+            Self::File { len, .. } => *len,
+            Self::Parts(parts) => parts.iter().map(Self::len).sum(),
+            Self::Stream(_) => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_stream(&self) -> bool {
+        matches!(self, Self::Stream(_))
+    }
+
+    /// The body's bytes, if it is held in memory.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    fn write_to(&self, stream: &mut impl Write) -> IoResult<()> {
+        match self {
+            Self::Bytes(bytes) => stream.write_all(bytes),
+            Self::File { file, start, len } => {
+                let mut file = file;
+                file.seek(SeekFrom::Start(*start))?;
+                let copied = io::copy(&mut file.take(*len), stream)?;
+                if copied < *len {
+                    // The file shrank after Content-Length was sent.
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                Ok(())
+            }
+            Self::Parts(parts) => parts.iter().try_for_each(|part| part.write_to(stream)),
+            Self::Stream(_) => Ok(()),
+        }
+    }
+}
+
+impl From<Vec<u8>> for ResponseBody {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::Bytes(bytes)
+    }
+}
+
+impl From<&[u8]> for ResponseBody {
+    fn from(bytes: &[u8]) -> Self {
+        Self::Bytes(bytes.to_vec())
+    }
+}
+
+impl From<String> for ResponseBody {
+    fn from(text: String) -> Self {
+        Self::Bytes(text.into_bytes())
+    }
+}
+
+impl From<BodyStream> for ResponseBody {
+    fn from(stream: BodyStream) -> Self {
+        Self::Stream(stream)
+    }
+}
+
+impl From<&str> for ResponseBody {
+    fn from(text: &str) -> Self {
+        Self::Bytes(text.as_bytes().to_vec())
+    }
 }
 
 impl Response {
-    pub fn new(status_code: StatusCode, body: Option<String>) -> Self {
+    pub fn new(status_code: StatusCode, body: Option<Vec<u8>>) -> Self {
         Response {
             status_code,
-            body
+            headers: Headers::new(),
+            body: body.map(ResponseBody::Bytes),
+            upgrade: None,
+        }
+    }
+
+    /// A response carrying the default HTML error page for `status_code`.
+    pub fn error(status_code: StatusCode) -> Self {
+        Self::new(status_code, None).with_error_page()
+    }
+
+    /// Fills in the default error page if this is an error response that
+    /// was left without a body.
+    pub fn with_error_page(mut self) -> Self {
+        let status_code = self.status_code;
+        if self.body.is_none() && (status_code.is_client_error() || status_code.is_server_error()) {
+            let title = format!("{} {}", status_code, status_code.reason_phrase());
+            let page = format!(
+                "<!DOCTYPE html>\n<html>\n  <head><title>{title}</title></head>\n  <body><h1>{title}</h1></body>\n</html>\n"
+            );
+            self.headers.insert("Content-Type", "text/html; charset=utf-8");
+            self.body = Some(page.into());
         }
+        self
+    }
+
+    pub fn builder() -> ResponseBuilder {
+        ResponseBuilder::new()
     }
+
+    pub fn status_code(&self) -> StatusCode {
+        self.status_code
+    }
+
+    pub fn headers(&self) -> &Headers<'static> {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut Headers<'static> {
+        &mut self.headers
+    }
+
+    /// Adds a `Set-Cookie` header for `cookie`.
+    pub fn set_cookie(&mut self, cookie: &Cookie) {
+        self.headers.append("Set-Cookie", cookie.to_string());
+    }
+
+    pub fn body(&self) -> Option<&ResponseBody> {
+        self.body.as_ref()
+    }
+
+    pub fn set_body(&mut self, body: impl Into<ResponseBody>) {
+        self.body = Some(body.into());
+    }
+
+    pub fn take_body(&mut self) -> Option<ResponseBody> {
+        self.body.take()
+    }
+
+    pub fn take_upgrade(&mut self) -> Option<Upgrade> {
+        self.upgrade.take()
+    }
+
+    /// Writes the response. `Content-Length` always reflects the body, so
+    /// any value set by hand is ignored, except on a `304 Not Modified`
+    /// with an empty body.
     pub fn send(&self, stream: &mut impl Write) -> IoResult<()> {
-        let body = match &self.body {
-            Some(b) => b,
-            None => "",
+        self.write_to(stream, true)
+    }
+
+    /// Writes the status line and headers only, as the answer to a HEAD
+    /// request. `Content-Length` still describes the body a GET would get:
+    /// the body's length, or the value set by hand if the body is empty.
+    pub fn send_head(&self, stream: &mut impl Write) -> IoResult<()> {
+        self.write_to(stream, false)
+    }
+
+    fn write_to(&self, stream: &mut impl Write, include_body: bool) -> IoResult<()> {
+        let body_len = self.body.as_ref().map_or(0, ResponseBody::len);
+        // These statuses never carry a body, so they get no length either.
+        let bodiless = self.status_code.is_informational()
+            || matches!(self.status_code, StatusCode::NoContent | StatusCode::NotModified);
+        // A streamed body runs until the connection closes.
+        let streamed = self.body.as_ref().is_some_and(ResponseBody::is_stream);
+        // With an empty body, a response can still pass on the length of the
+        // one it stands for, as a relayed HEAD or 304 answer does.
+        let empty = self.body.as_ref().is_none_or(|body| body.as_bytes().is_some_and(<[u8]>::is_empty));
+        let declared_len = match empty && ((!include_body && !bodiless) || self.status_code == StatusCode::NotModified) {
+            true => self.headers.get("Content-Length").and_then(|len| len.trim().parse::<u64>().ok()),
+            false => None,
         };
-        write!(stream, 
-            "HTTP/1.1 {} {}\r\n\r\n{}", 
-            self.status_code, 
-            self.status_code.reason_phrase(),
-            body
-            )
+
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\n",
+            self.status_code,
+            self.status_code.reason_phrase()
+        );
+        for (name, value) in self.headers.iter() {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                continue;
+            }
+            // A line break in a value would let it smuggle in extra headers.
+            let value = value.replace(['\r', '\n'], " ");
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        if let Some(len) = declared_len {
+            head.push_str(&format!("Content-Length: {len}\r\n"));
+        } else if !bodiless && !streamed {
+            head.push_str(&format!("Content-Length: {}\r\n", body_len));
+        }
+        head.push_str("\r\n");
+
+        stream.write_all(head.as_bytes())?;
+        if include_body && !bodiless && let Some(body) = &self.body {
+            body.write_to(stream)?;
+        }
+        stream.flush()
+    }
+}
+
+/// Builds a `Response` step by step:
+/// `Response::builder().status(StatusCode::OK).header("Cache-Control", "no-cache").body(html)`.
+#[derive(Debug)]
+pub struct ResponseBuilder {
+    status_code: StatusCode,
+    headers: Headers<'static>,
+    upgrade: Option<Upgrade>,
+}
+
+impl ResponseBuilder {
+    pub fn new() -> Self {
+        Self {
+            status_code: StatusCode::OK,
+            headers: Headers::new(),
+            upgrade: None,
+        }
+    }
+
+    pub fn status(mut self, status_code: StatusCode) -> Self {
+        self.status_code = status_code;
+        self
+    }
+
+    /// Adds a header, keeping earlier ones with the same name.
+    pub fn header(mut self, name: impl Into<Cow<'static, str>>, value: impl Into<Cow<'static, str>>) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn cookie(mut self, cookie: &Cookie) -> Self {
+        self.headers.append("Set-Cookie", cookie.to_string());
+        self
+    }
+
+    /// Hands the connection to `upgrade` after this response is sent. Only
+    /// meaningful with `101 Switching Protocols`.
+    pub fn upgrade(mut self, upgrade: Upgrade) -> Self {
+        self.upgrade = Some(upgrade);
+        self
+    }
+
+    pub fn body(self, body: impl Into<ResponseBody>) -> Response {
+        self.finish(Some(body.into()))
+    }
+
+    /// Finishes the response without a body.
+    pub fn build(self) -> Response {
+        self.finish(None)
+    }
+
+    fn finish(self, body: Option<ResponseBody>) -> Response {
+        Response {
+            status_code: self.status_code,
+            headers: self.headers,
+            body,
+            upgrade: self.upgrade,
+        }
+    }
+}
+
+impl Default for ResponseBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sent(response: &Response) -> Vec<u8> {
+        let mut sent = Vec::new();
+        response.send(&mut sent).unwrap();
+        sent
+    }
+
+    #[test]
+    fn builder_sets_status_headers_and_body() {
+        let response = Response::builder()
+            .status(StatusCode::Created)
+            .header("Location", "/items/1")
+            .header("Set-Cookie", "a=1")
+            .header("Set-Cookie", "b=2")
+            .body("made");
+
+        assert_eq!(
+            sent(&response),
+            b"HTTP/1.1 201 Created\r\nLocation: /items/1\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\nContent-Length: 4\r\n\r\nmade"
+        );
+    }
+
+    #[test]
+    fn content_length_always_matches_the_body() {
+        let mut response = Response::new(StatusCode::OK, Some("héllo".into()));
+        response.headers_mut().insert("Content-Length", "999");
+        assert_eq!(sent(&response), "HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nhéllo".as_bytes());
+
+        let empty = Response::new(StatusCode::OK, None);
+        assert_eq!(sent(&empty), b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+
+        let mut head = Vec::new();
+        response.send_head(&mut head).unwrap();
+        assert_eq!(head, b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\n");
+    }
+
+    #[test]
+    fn binary_bodies_are_sent_byte_for_byte() {
+        let bytes = vec![0x89, b'P', b'N', b'G', 0x00, 0xff, 0xfe, b'\r', b'\n'];
+        let response = Response::builder()
+            .header("Content-Type", "image/png")
+            .body(bytes.clone());
+
+        let sent = sent(&response);
+        let head = b"HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: 9\r\n\r\n";
+        assert_eq!(&sent[..head.len()], head);
+        assert_eq!(&sent[head.len()..], bytes);
     }
 }
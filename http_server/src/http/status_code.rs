@@ -1,20 +1,123 @@
 use std::fmt::{Display, Formatter, Result as FmtResult};
 
+/// Declares every status code once, with its reason phrase, so the enum,
+/// `reason_phrase` and `from_u16` can't drift apart.
+macro_rules! status_codes {
+    ($($name:ident = $code:literal => $phrase:literal,)+) => {
+        #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+        pub enum StatusCode {
+            $($name = $code,)+
+        }
+
+        impl StatusCode {
+            pub fn reason_phrase(&self) -> &str {
+                match self {
+                    $(Self::$name => $phrase,)+
+                }
+            }
 
-#[derive(Copy, Clone, Debug)]
-pub enum StatusCode {
-    OK = 200,
-    BadRequest = 400,
-    NotFound = 404,
+            pub fn from_u16(code: u16) -> Option<Self> {
+                match code {
+                    $($code => Some(Self::$name),)+
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+status_codes! {
+    Continue = 100 => "Continue",
+    SwitchingProtocols = 101 => "Switching Protocols",
+    Processing = 102 => "Processing",
+    EarlyHints = 103 => "Early Hints",
+
+    OK = 200 => "OK",
+    Created = 201 => "Created",
+    Accepted = 202 => "Accepted",
+    NonAuthoritativeInformation = 203 => "Non-Authoritative Information",
+    NoContent = 204 => "No Content",
+    ResetContent = 205 => "Reset Content",
+    PartialContent = 206 => "Partial Content",
+    MultiStatus = 207 => "Multi-Status",
+    AlreadyReported = 208 => "Already Reported",
+    ImUsed = 226 => "IM Used",
+
+    MultipleChoices = 300 => "Multiple Choices",
+    MovedPermanently = 301 => "Moved Permanently",
+    Found = 302 => "Found",
+    SeeOther = 303 => "See Other",
+    NotModified = 304 => "Not Modified",
+    UseProxy = 305 => "Use Proxy",
+    TemporaryRedirect = 307 => "Temporary Redirect",
+    PermanentRedirect = 308 => "Permanent Redirect",
+
+    BadRequest = 400 => "Bad Request",
+    Unauthorized = 401 => "Unauthorized",
+    PaymentRequired = 402 => "Payment Required",
+    Forbidden = 403 => "Forbidden",
+    NotFound = 404 => "Not Found",
+    MethodNotAllowed = 405 => "Method Not Allowed",
+    NotAcceptable = 406 => "Not Acceptable",
+    ProxyAuthenticationRequired = 407 => "Proxy Authentication Required",
+    RequestTimeout = 408 => "Request Timeout",
+    Conflict = 409 => "Conflict",
+    Gone = 410 => "Gone",
+    LengthRequired = 411 => "Length Required",
+    PreconditionFailed = 412 => "Precondition Failed",
+    PayloadTooLarge = 413 => "Payload Too Large",
+    UriTooLong = 414 => "URI Too Long",
+    UnsupportedMediaType = 415 => "Unsupported Media Type",
+    RangeNotSatisfiable = 416 => "Range Not Satisfiable",
+    ExpectationFailed = 417 => "Expectation Failed",
+    ImATeapot = 418 => "I'm a teapot",
+    MisdirectedRequest = 421 => "Misdirected Request",
+    UnprocessableEntity = 422 => "Unprocessable Entity",
+    Locked = 423 => "Locked",
+    FailedDependency = 424 => "Failed Dependency",
+    TooEarly = 425 => "Too Early",
+    UpgradeRequired = 426 => "Upgrade Required",
+    PreconditionRequired = 428 => "Precondition Required",
+    TooManyRequests = 429 => "Too Many Requests",
+    RequestHeaderFieldsTooLarge = 431 => "Request Header Fields Too Large",
+    UnavailableForLegalReasons = 451 => "Unavailable For Legal Reasons",
+
+    InternalServerError = 500 => "Internal Server Error",
+    NotImplemented = 501 => "Not Implemented",
+    BadGateway = 502 => "Bad Gateway",
+    ServiceUnavailable = 503 => "Service Unavailable",
+    GatewayTimeout = 504 => "Gateway Timeout",
+    HttpVersionNotSupported = 505 => "HTTP Version Not Supported",
+    VariantAlsoNegotiates = 506 => "Variant Also Negotiates",
+    InsufficientStorage = 507 => "Insufficient Storage",
+    LoopDetected = 508 => "Loop Detected",
+    NotExtended = 510 => "Not Extended",
+    NetworkAuthenticationRequired = 511 => "Network Authentication Required",
 }
 
 impl StatusCode {
-    pub fn reason_phrase(&self) -> &str {
-        match self {
-            Self::OK => "OK",
-            Self::BadRequest =>"Bad Request",
-            Self::NotFound => "Not Found",
-        }
+    pub fn as_u16(&self) -> u16 {
+        *self as u16
+    }
+
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.as_u16())
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.as_u16())
+    }
+
+    pub fn is_redirection(&self) -> bool {
+        (300..400).contains(&self.as_u16())
+    }
+
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.as_u16())
+    }
+
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.as_u16())
     }
 }
 
@@ -23,3 +126,44 @@ impl Display for StatusCode {
         write!(f, "{}", *self as u16)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Response;
+
+    #[test]
+    fn every_code_round_trips_and_falls_in_one_class() {
+        let codes: Vec<StatusCode> = (0..1000).filter_map(StatusCode::from_u16).collect();
+        assert_eq!(codes.len(), 62);
+
+        for status in codes {
+            let code = status.as_u16();
+            assert_eq!(StatusCode::from_u16(code), Some(status));
+            assert_eq!(status.to_string(), code.to_string());
+            assert!(!status.reason_phrase().is_empty());
+
+            let classes = [
+                status.is_informational(),
+                status.is_success(),
+                status.is_redirection(),
+                status.is_client_error(),
+                status.is_server_error(),
+            ];
+            assert_eq!(classes.iter().filter(|&&class| class).count(), 1);
+            assert!(classes[usize::from(code / 100) - 1]);
+        }
+        assert_eq!(StatusCode::from_u16(299), None);
+    }
+
+    #[test]
+    fn error_page_names_the_status() {
+        let response = Response::error(StatusCode::NotFound);
+        let page = String::from_utf8(response.body().and_then(|body| body.as_bytes()).unwrap().to_vec()).unwrap();
+
+        assert_eq!(response.headers().get("Content-Type"), Some("text/html; charset=utf-8"));
+        assert!(page.contains("<title>404 Not Found</title>"));
+        assert!(page.contains("<h1>404 Not Found</h1>"));
+        assert!(Response::error(StatusCode::OK).body().is_none());
+    }
+}
@@ -1,18 +1,38 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
 #![allow(unused_imports)]
+#![allow(clippy::upper_case_acronyms)]
 use http::Method;
 use http::Request;
-use server::Server;
+use config::{Args, Config, USAGE};
+use shutdown::ShutdownHandle;
 use std::env;
+use std::process;
+use std::thread;
 
 
 use website_handler::WebsiteHandler;
 
 
 mod server;
+mod router;
+mod middleware;
+mod access_log;
+mod shutdown;
+mod session;
+mod auth;
+mod websocket;
+mod sse;
+mod proxy;
+mod virtual_host;
+mod config;
+mod thread_pool;
+mod tls;
+#[cfg(test)]
+mod test_util;
 mod http;
 mod website_handler;
+mod directory_listing;
 
 fn main() {
     /*
@@ -37,9 +57,48 @@ fn main() {
     //let delete = Method::DELETE;
     //let post = Method::POST;
     //let put = Method::PUT;
-    let default_path = format!("{}/public" ,env!("CARGO_MANIFEST_DIR"));
-    let public_path = env::var("PUBLIC_PATH").unwrap_or(default_path);
-    println!("the public path: {public_path}");
-    let mut server = Server::new("127.0.0.1:8180".to_string());
-    server.run(WebsiteHandler::new(public_path));
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+    if args.help {
+        println!("{USAGE}");
+        return;
+    }
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {e}");
+            process::exit(1);
+        }
+    };
+    if args.check_config {
+        println!("Configuration OK");
+        return;
+    }
+
+    let shutdown = ShutdownHandle::default();
+    let servers = match config.servers(&shutdown) {
+        Ok(servers) => servers,
+        Err(e) => {
+            eprintln!("Invalid configuration: {e}");
+            process::exit(1);
+        }
+    };
+    if let Err(e) = shutdown.shutdown_on_signals() {
+        println!("Failed to install signal handler: {e}");
+    }
+    let running: Vec<_> = servers
+        .into_iter()
+        .map(|mut server| {
+            let handler = config.handler();
+            thread::spawn(move || server.run(handler))
+        })
+        .collect();
+    for server in running {
+        let _ = server.join();
+    }
 }
@@ -0,0 +1,385 @@
+use super::http::{Method, Request, Response, StatusCode};
+use super::server::Handler;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Code that runs around a handler. It gets the request first, may change
+/// it or answer it outright, and otherwise calls `next.run(request)` and
+/// gets to look at the response on its way out.
+///
+/// Plain functions of the form `fn(Request, Next) -> Response` are
+/// middleware already.
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: Request<'_>, next: Next<'_>) -> Response;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(Request<'_>, Next<'_>) -> Response + Send + Sync,
+{
+    fn handle(&self, request: Request<'_>, next: Next<'_>) -> Response {
+        self(request, next)
+    }
+}
+
+/// The rest of the chain: any middleware still to run, then the handler.
+pub struct Next<'a> {
+    middleware: &'a [Box<dyn Middleware>],
+    handler: &'a dyn Handler,
+}
+
+impl Next<'_> {
+    pub fn run(self, request: Request<'_>) -> Response {
+        match self.middleware.split_first() {
+            Some((middleware, rest)) => middleware.handle(
+                request,
+                Next {
+                    middleware: rest,
+                    handler: self.handler,
+                },
+            ),
+            None => self.handler.handle_request(&request),
+        }
+    }
+}
+
+/// A handler wrapped in middleware, itself usable as a `Handler`.
+///
+/// Middleware runs in the order it was added, so the first one added sees
+/// the request first and the response last:
+///
+/// ```ignore
+/// let handler = Chain::new(WebsiteHandler::new(public_path))
+///     .wrap(middleware::catch_panic)
+///     .wrap(middleware::request_id)
+///     .wrap(Cors::new().with_origins(vec!["https://example.com".to_string()]));
+/// ```
+pub struct Chain<H> {
+    middleware: Vec<Box<dyn Middleware>>,
+    handler: H,
+}
+
+impl<H: Handler> Chain<H> {
+    pub fn new(handler: H) -> Self {
+        Self {
+            middleware: Vec::new(),
+            handler,
+        }
+    }
+
+    pub fn wrap(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+}
+
+impl<H: Handler> Handler for Chain<H> {
+    fn handle_request(&self, request: &Request) -> Response {
+        let next = Next {
+            middleware: &self.middleware,
+            handler: &self.handler,
+        };
+        next.run(request.clone())
+    }
+
+    fn handle_bad_request(&self, e: &crate::http::ParseError) -> Response {
+        self.handler.handle_bad_request(e)
+    }
+}
+
+/// Prints one line per request with its status and how long it took.
+pub fn logger(request: Request<'_>, next: Next<'_>) -> Response {
+    let started = Instant::now();
+    let line = format!("{} {}", request.method(), request.target());
+    let response = next.run(request);
+    println!("{line} -> {} ({:.1?})", response.status_code(), started.elapsed());
+    response
+}
+
+/// Makes sure every request carries an `X-Request-Id`, keeping one the
+/// client or a proxy sent, and echoes it on the response.
+pub fn request_id(mut request: Request<'_>, next: Next<'_>) -> Response {
+    let id = match request.header("X-Request-Id") {
+        Some(id) if is_valid_request_id(id) => id.to_string(),
+        _ => new_request_id(),
+    };
+    request.headers_mut().insert("X-Request-Id", id.clone());
+
+    let mut response = next.run(request);
+    response.headers_mut().insert("X-Request-Id", id);
+    response
+}
+
+/// Reports how long the rest of the chain took in a `Server-Timing`
+/// header, e.g. `Server-Timing: app;dur=1.25`.
+pub fn timing(request: Request<'_>, next: Next<'_>) -> Response {
+    let started = Instant::now();
+    let mut response = next.run(request);
+    let millis = started.elapsed().as_secs_f64() * 1000.0;
+    response.headers_mut().append("Server-Timing", format!("app;dur={millis:.2}"));
+    response
+}
+
+/// Turns a panic further down the chain into a 500, so the client gets an
+/// answer instead of a dropped connection. Add it first to cover the most.
+pub fn catch_panic(request: Request<'_>, next: Next<'_>) -> Response {
+    let target = request.target().to_string();
+    match panic::catch_unwind(AssertUnwindSafe(|| next.run(request))) {
+        Ok(response) => response,
+        Err(_) => {
+            println!("Handler panicked while serving {target}");
+            Response::error(StatusCode::InternalServerError)
+        }
+    }
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    (1..=200).contains(&id.len())
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
+
+/// Unique within the process and unpredictable enough to not collide with
+/// ids from other instances.
+fn new_request_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{:016x}{count:08x}", RandomState::new().hash_one(count))
+}
+
+/// Answers CORS preflight requests and adds the `Access-Control-*`
+/// headers browsers need to let other origins read responses.
+#[derive(Debug, Clone)]
+pub struct Cors {
+    origins: Option<Vec<String>>,
+    methods: String,
+    headers: Option<String>,
+    expose_headers: Option<String>,
+    credentials: bool,
+    max_age: Option<u64>,
+}
+
+impl Cors {
+    /// Allows any origin to make simple requests with the usual methods.
+    pub fn new() -> Self {
+        Self {
+            origins: None,
+            methods: "GET, HEAD, POST, PUT, DELETE, OPTIONS".to_string(),
+            headers: None,
+            expose_headers: None,
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    /// Only these origins, e.g. `https://example.com`, are allowed.
+    pub fn with_origins(mut self, origins: Vec<String>) -> Self {
+        self.origins = Some(origins);
+        self
+    }
+
+    pub fn with_methods(mut self, methods: &[Method]) -> Self {
+        let methods: Vec<&str> = methods.iter().map(Method::as_str).collect();
+        self.methods = methods.join(", ");
+        self
+    }
+
+    /// Request headers allowed in preflighted requests. Without this, any
+    /// headers the preflight asks for are allowed.
+    pub fn with_headers(mut self, headers: &[&str]) -> Self {
+        self.headers = Some(headers.join(", "));
+        self
+    }
+
+    /// Response headers scripts on the other origin may read.
+    pub fn with_expose_headers(mut self, headers: &[&str]) -> Self {
+        self.expose_headers = Some(headers.join(", "));
+        self
+    }
+
+    /// Whether cookies and auth headers may be sent along. The allowed
+    /// origin is then echoed back rather than `*`. This only takes effect
+    /// together with `with_origins`: letting any site make credentialed
+    /// requests would let any site read what the user can, so without a
+    /// list no origin is allowed at all.
+    pub fn with_credentials(mut self, credentials: bool) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// How long, in seconds, browsers may cache a preflight answer.
+    pub fn with_max_age(mut self, seconds: u64) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    /// The `Access-Control-Allow-Origin` value for `origin`, if allowed.
+    fn allow_origin(&self, origin: &str) -> Option<String> {
+        match &self.origins {
+            Some(origins) => origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+                .then(|| origin.to_string()),
+            None if self.credentials => None,
+            None => Some("*".to_string()),
+        }
+    }
+
+    fn add_origin_headers(&self, response: &mut Response, allow_origin: String) {
+        let headers = response.headers_mut();
+        headers.insert("Access-Control-Allow-Origin", allow_origin);
+        if self.credentials {
+            headers.insert("Access-Control-Allow-Credentials", "true");
+        }
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for Cors {
+    fn handle(&self, request: Request<'_>, next: Next<'_>) -> Response {
+        let mut response = self.respond(request, next);
+        // With an origin list, every answer depends on the Origin sent, even
+        // one without CORS headers, so caches must keep them apart.
+        if self.origins.is_some() && !response.headers().contains_token("Vary", "Origin") {
+            response.headers_mut().append("Vary", "Origin");
+        }
+        response
+    }
+}
+
+impl Cors {
+    fn respond(&self, request: Request<'_>, next: Next<'_>) -> Response {
+        let Some(origin) = request.header("Origin").map(str::to_string) else {
+            return next.run(request);
+        };
+        let allow_origin = self.allow_origin(&origin);
+
+        let preflight = *request.method() == Method::OPTIONS
+            && request.header("Access-Control-Request-Method").is_some();
+        if preflight {
+            let mut response = Response::new(StatusCode::NoContent, None);
+            let Some(allow_origin) = allow_origin else {
+                return response;
+            };
+            self.add_origin_headers(&mut response, allow_origin);
+            let headers = response.headers_mut();
+            headers.insert("Access-Control-Allow-Methods", self.methods.clone());
+            let allow_headers = self
+                .headers
+                .clone()
+                .or_else(|| request.header("Access-Control-Request-Headers").map(str::to_string));
+            if let Some(allow_headers) = allow_headers {
+                headers.insert("Access-Control-Allow-Headers", allow_headers);
+            }
+            if let Some(max_age) = self.max_age {
+                headers.insert("Access-Control-Max-Age", max_age.to_string());
+            }
+            return response;
+        }
+
+        let mut response = next.run(request);
+        if let Some(allow_origin) = allow_origin {
+            self.add_origin_headers(&mut response, allow_origin);
+            if let Some(expose_headers) = &self.expose_headers {
+                response.headers_mut().insert("Access-Control-Expose-Headers", expose_headers.clone());
+            }
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(handler: &impl Handler, raw: &str) -> Response {
+        let request = Request::try_from(raw.as_bytes()).unwrap();
+        handler.handle_request(&request)
+    }
+
+    fn echo_request_id(request: &Request) -> Response {
+        let id = request.header("X-Request-Id").unwrap_or_default().to_string();
+        Response::new(StatusCode::OK, Some(id.into_bytes()))
+    }
+
+    #[test]
+    fn runs_middleware_in_order_around_the_handler() {
+        fn tag(name: &'static str) -> impl Fn(Request<'_>, Next<'_>) -> Response + Send + Sync {
+            move |request, next| {
+                let mut response = next.run(request);
+                response.headers_mut().append("X-Order", name);
+                response
+            }
+        }
+        let chain = Chain::new(echo_request_id).wrap(tag("outer")).wrap(tag("inner"));
+
+        let response = send(&chain, "GET / HTTP/1.1\r\n\r\n");
+
+        let order: Vec<&str> = response.headers().get_all("X-Order").collect();
+        assert_eq!(order, ["inner", "outer"]);
+    }
+
+    #[test]
+    fn request_id_is_kept_or_generated() {
+        let chain = Chain::new(echo_request_id).wrap(request_id);
+
+        let kept = send(&chain, "GET / HTTP/1.1\r\nX-Request-Id: abc-123\r\n\r\n");
+        let generated = send(&chain, "GET / HTTP/1.1\r\nX-Request-Id: bad id\r\n\r\n");
+
+        assert_eq!(kept.headers().get("X-Request-Id"), Some("abc-123"));
+        let id = generated.headers().get("X-Request-Id").unwrap();
+        assert_ne!(id, "bad id");
+        assert_eq!(generated.body().and_then(|body| body.as_bytes()), Some(id.as_bytes()));
+    }
+
+    #[test]
+    fn panics_become_500() {
+        let chain = Chain::new(|_: &Request| -> Response { panic!("boom") }).wrap(catch_panic);
+
+        assert_eq!(send(&chain, "GET / HTTP/1.1\r\n\r\n").status_code(), StatusCode::InternalServerError);
+    }
+
+    #[test]
+    fn cors_answers_preflight_for_allowed_origins() {
+        let cors = Cors::new()
+            .with_origins(vec!["https://app.example".to_string()])
+            .with_max_age(600);
+        let chain = Chain::new(echo_request_id).wrap(cors);
+
+        let preflight = send(
+            &chain,
+            "OPTIONS /api HTTP/1.1\r\nOrigin: https://app.example\r\nAccess-Control-Request-Method: PUT\r\nAccess-Control-Request-Headers: content-type\r\n\r\n",
+        );
+        let other = send(&chain, "GET /api HTTP/1.1\r\nOrigin: https://evil.example\r\n\r\n");
+
+        assert_eq!(preflight.status_code(), StatusCode::NoContent);
+        assert_eq!(preflight.headers().get("Access-Control-Allow-Origin"), Some("https://app.example"));
+        assert_eq!(preflight.headers().get("Access-Control-Allow-Headers"), Some("content-type"));
+        assert_eq!(preflight.headers().get("Access-Control-Max-Age"), Some("600"));
+        assert_eq!(other.status_code(), StatusCode::OK);
+        assert!(!other.headers().contains("Access-Control-Allow-Origin"));
+
+        let none = send(&chain, "GET /api HTTP/1.1\r\n\r\n");
+        for response in [&preflight, &other, &none] {
+            assert_eq!(response.headers().get("Vary"), Some("Origin"));
+        }
+        let open = send(&Chain::new(echo_request_id).wrap(Cors::new()), "GET /api HTTP/1.1\r\nOrigin: https://a.example\r\n\r\n");
+        assert!(!open.headers().contains("Vary"));
+    }
+
+    #[test]
+    fn cors_credentials_need_an_origin_list() {
+        let chain = Chain::new(echo_request_id).wrap(Cors::new().with_credentials(true));
+        let response = send(&chain, "GET /api HTTP/1.1\r\nOrigin: https://evil.example\r\n\r\n");
+
+        assert!(!response.headers().contains("Access-Control-Allow-Origin"));
+        assert!(!response.headers().contains("Access-Control-Allow-Credentials"));
+    }
+}
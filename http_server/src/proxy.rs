@@ -0,0 +1,561 @@
+use super::http::body::{self, BodyLength};
+use super::http::{Headers, Method, Request, Response, StatusCode};
+use super::server::Handler;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long connecting to an upstream may take by default.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long an upstream may go without sending anything by default.
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(60);
+/// Largest upstream response body passed on by default.
+const DEFAULT_MAX_RESPONSE_SIZE: usize = 16 * 1024 * 1024;
+/// Failures in a row that take an upstream out of rotation by default.
+const DEFAULT_MAX_FAILS: u32 = 3;
+/// How long an upstream stays out of rotation by default.
+const DEFAULT_FAIL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Headers that describe one connection rather than the message, so they
+/// are never passed through.
+const HOP_BY_HOP: [&str; 9] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// Forwards requests to upstream HTTP servers chosen by path prefix.
+///
+/// The longest matching prefix decides, and paths under none get 404. The
+/// request goes upstream with its target unchanged, `Host` set to the
+/// upstream's address and `X-Forwarded-For`, `-Proto` and `-Host` added.
+/// Upstreams that keep failing are skipped for a while. Failures come back
+/// as 502 Bad Gateway, or 504 Gateway Timeout if the upstream was too slow.
+///
+/// ```ignore
+/// let proxy = ProxyHandler::new()
+///     .route("/api", Upstreams::new(["10.0.0.1:8080", "10.0.0.2:8080"]).with_balance(Balance::LeastConnections))
+///     .route("/", Upstreams::new(["127.0.0.1:3000"]));
+/// ```
+#[derive(Debug)]
+pub struct ProxyHandler {
+    routes: Vec<Route>,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    max_response_size: usize,
+    max_fails: u32,
+    fail_timeout: Duration,
+}
+
+#[derive(Debug)]
+struct Route {
+    prefix: String,
+    upstreams: Upstreams,
+}
+
+/// How a request picks one of several upstreams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Balance {
+    /// Each in turn.
+    #[default]
+    RoundRobin,
+    /// Whichever has the fewest requests in flight.
+    LeastConnections,
+}
+
+/// The servers one route can be forwarded to, as `host:port` addresses.
+#[derive(Debug)]
+pub struct Upstreams {
+    servers: Vec<Upstream>,
+    balance: Balance,
+    next: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct Upstream {
+    addr: String,
+    active: AtomicUsize,
+    health: Mutex<Health>,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    fails: u32,
+    down_until: Option<Instant>,
+}
+
+/// Counts a request against its upstream while it is in flight.
+struct Active<'a>(&'a Upstream);
+
+impl<'a> Active<'a> {
+    fn new(upstream: &'a Upstream) -> Self {
+        upstream.active.fetch_add(1, Ordering::SeqCst);
+        Self(upstream)
+    }
+}
+
+impl Drop for Active<'_> {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug)]
+enum ProxyError {
+    /// Nothing was sent, so another upstream can be tried.
+    Connect(io::Error),
+    Timeout,
+    Io(io::Error),
+    InvalidResponse,
+    ResponseTooLarge,
+}
+
+impl ProxyError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Timeout => StatusCode::GatewayTimeout,
+            Self::Connect(e) if is_timeout(e) => StatusCode::GatewayTimeout,
+            _ => StatusCode::BadGateway,
+        }
+    }
+}
+
+impl Display for ProxyError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Connect(e) => write!(f, "failed to connect: {e}"),
+            Self::Timeout => write!(f, "timed out"),
+            Self::Io(e) => write!(f, "{e}"),
+            Self::InvalidResponse => write!(f, "invalid response"),
+            Self::ResponseTooLarge => write!(f, "response too large"),
+        }
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+impl Upstreams {
+    pub fn new(addrs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            servers: addrs
+                .into_iter()
+                .map(|addr| Upstream {
+                    addr: addr.into(),
+                    active: AtomicUsize::new(0),
+                    health: Mutex::default(),
+                })
+                .collect(),
+            balance: Balance::default(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn with_balance(mut self, balance: Balance) -> Self {
+        self.balance = balance;
+        self
+    }
+
+    /// The upstreams in rotation, in the order to try them.
+    fn candidates(&self) -> Vec<&Upstream> {
+        let now = Instant::now();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let len = self.servers.len().max(1);
+        let mut candidates: Vec<&Upstream> = (0..self.servers.len())
+            .map(|i| &self.servers[(start + i) % len])
+            .filter(|upstream| upstream.is_up(now))
+            .collect();
+        if self.balance == Balance::LeastConnections {
+            // Stable, so ties still take turns.
+            candidates.sort_by_key(|upstream| upstream.active.load(Ordering::SeqCst));
+        }
+        candidates
+    }
+}
+
+impl Upstream {
+    fn is_up(&self, now: Instant) -> bool {
+        let health = self.health.lock().unwrap_or_else(|e| e.into_inner());
+        health.down_until.is_none_or(|until| until <= now)
+    }
+
+    fn record(&self, ok: bool, max_fails: u32, fail_timeout: Duration) {
+        let mut health = self.health.lock().unwrap_or_else(|e| e.into_inner());
+        if ok {
+            *health = Health::default();
+            return;
+        }
+        health.fails += 1;
+        if health.fails >= max_fails {
+            health.down_until = Some(Instant::now() + fail_timeout);
+        }
+    }
+}
+
+impl ProxyHandler {
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+            max_fails: DEFAULT_MAX_FAILS,
+            fail_timeout: DEFAULT_FAIL_TIMEOUT,
+        }
+    }
+
+    /// Forwards requests under `prefix` to `upstreams`.
+    pub fn route(mut self, prefix: &str, upstreams: Upstreams) -> Self {
+        self.routes.push(Route {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            upstreams,
+        });
+        self
+    }
+
+    /// Connecting may take up to `connect`, and the upstream may then go
+    /// quiet for up to `read` at a time before the request gets 504.
+    pub fn with_timeouts(mut self, connect: Duration, read: Duration) -> Self {
+        self.connect_timeout = connect;
+        self.read_timeout = read;
+        self
+    }
+
+    /// Upstream responses with a larger body are answered with 502.
+    pub fn with_max_response_size(mut self, max_response_size: usize) -> Self {
+        self.max_response_size = max_response_size;
+        self
+    }
+
+    /// After `max_fails` failures in a row an upstream is left out for
+    /// `fail_timeout`, then given another chance.
+    pub fn with_health_check(mut self, max_fails: u32, fail_timeout: Duration) -> Self {
+        self.max_fails = max_fails.max(1);
+        self.fail_timeout = fail_timeout;
+        self
+    }
+
+    fn route_for(&self, path: &str) -> Option<&Route> {
+        self.routes
+            .iter()
+            .filter(|route| {
+                path.strip_prefix(&route.prefix)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .max_by_key(|route| route.prefix.len())
+    }
+
+    fn forward(&self, upstream: &Upstream, request: &Request) -> Result<Response, ProxyError> {
+        let mut stream = connect(&upstream.addr, self.connect_timeout).map_err(ProxyError::Connect)?;
+        stream.set_read_timeout(Some(self.read_timeout)).map_err(ProxyError::Io)?;
+        stream.set_write_timeout(Some(self.read_timeout)).map_err(ProxyError::Io)?;
+
+        stream.write_all(&upstream_request(request, &upstream.addr)).map_err(ProxyError::Io)?;
+        stream.flush().map_err(ProxyError::Io)?;
+
+        let head_only = *request.method() == Method::HEAD;
+        read_response(&mut stream, head_only, self.max_response_size)
+    }
+}
+
+impl Default for ProxyHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Handler for ProxyHandler {
+    fn handle_request(&self, request: &Request) -> Response {
+        let Some(route) = self.route_for(request.path()) else {
+            return Response::error(StatusCode::NotFound);
+        };
+
+        let mut status_code = StatusCode::BadGateway;
+        for upstream in route.upstreams.candidates() {
+            let _active = Active::new(upstream);
+            let result = self.forward(upstream, request);
+            upstream.record(result.is_ok(), self.max_fails, self.fail_timeout);
+            match result {
+                Ok(response) => return response,
+                Err(e) => {
+                    println!("Proxying to {} failed: {}", upstream.addr, e);
+                    status_code = e.status_code();
+                    // Only a request that never left can safely go elsewhere.
+                    if !matches!(e, ProxyError::Connect(_)) {
+                        break;
+                    }
+                }
+            }
+        }
+        Response::error(status_code)
+    }
+}
+
+fn connect(addr: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(ErrorKind::NotFound, "address did not resolve");
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Whether `name` is hop-by-hop, either always or because `headers` lists it
+/// in `Connection`.
+fn is_hop_by_hop(name: &str, headers: &Headers) -> bool {
+    HOP_BY_HOP.iter().any(|hop| hop.eq_ignore_ascii_case(name)) || headers.contains_token("Connection", name)
+}
+
+/// The request as sent to the upstream, on a connection used just once.
+fn upstream_request(request: &Request, upstream_addr: &str) -> Vec<u8> {
+    let headers = request.headers();
+    let mut head = format!("{} {} HTTP/1.1\r\nHost: {upstream_addr}\r\n", request.method(), request.target());
+    for (name, value) in headers.iter() {
+        let skip = is_hop_by_hop(name, headers)
+            || ["Host", "Content-Length", "Expect", "X-Forwarded-For", "X-Forwarded-Proto", "X-Forwarded-Host"]
+                .iter()
+                .any(|own| own.eq_ignore_ascii_case(name));
+        if !skip {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+    }
+
+    let forwarded_for = headers.get_all("X-Forwarded-For").map(str::trim).filter(|value| !value.is_empty());
+    let forwarded_for: Vec<String> = forwarded_for
+        .map(str::to_string)
+        .chain(request.remote_addr().map(|addr| addr.to_string()))
+        .collect();
+    if !forwarded_for.is_empty() {
+        head.push_str(&format!("X-Forwarded-For: {}\r\n", forwarded_for.join(", ")));
+    }
+    let proto = if request.is_secure() { "https" } else { "http" };
+    head.push_str(&format!("X-Forwarded-Proto: {proto}\r\n"));
+    if let Some(host) = request.header("Host") {
+        head.push_str(&format!("X-Forwarded-Host: {host}\r\n"));
+    }
+    let body = request.body();
+    if !body.is_empty() || matches!(request.method(), Method::POST | Method::PUT | Method::PATCH) {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+
+    let mut message = head.into_bytes();
+    message.extend_from_slice(body);
+    message
+}
+
+/// Reads more of the upstream's response into `buffer`, returning whether
+/// it has closed the connection.
+fn fill(stream: &mut TcpStream, buffer: &mut Vec<u8>, limit: usize) -> Result<bool, ProxyError> {
+    let mut chunk = [0; 8192];
+    match stream.read(&mut chunk) {
+        Ok(0) => Ok(true),
+        Ok(n) if buffer.len() + n > limit => Err(ProxyError::ResponseTooLarge),
+        Ok(n) => {
+            buffer.extend_from_slice(&chunk[..n]);
+            Ok(false)
+        }
+        Err(e) if is_timeout(&e) => Err(ProxyError::Timeout),
+        Err(e) => Err(ProxyError::Io(e)),
+    }
+}
+
+fn read_response(stream: &mut TcpStream, head_only: bool, max_response_size: usize) -> Result<Response, ProxyError> {
+    // Room for the head on top of the largest body.
+    let limit = max_response_size.saturating_add(64 * 1024);
+    let mut buffer = Vec::new();
+    loop {
+        let head_len = loop {
+            if let Some(head_len) = Request::head_len(&buffer) {
+                break head_len;
+            }
+            if fill(stream, &mut buffer, limit)? {
+                return Err(ProxyError::InvalidResponse);
+            }
+        };
+        let head = std::str::from_utf8(&buffer[..head_len]).map_err(|_| ProxyError::InvalidResponse)?;
+        let (status_line, header_block) = head.split_once("\r\n").ok_or(ProxyError::InvalidResponse)?;
+        let status_code = status_line
+            .strip_prefix("HTTP/1.")
+            .and_then(|rest| rest.get(2..5))
+            .and_then(|code| code.parse().ok())
+            .and_then(StatusCode::from_u16)
+            .ok_or(ProxyError::InvalidResponse)?;
+        let headers = Headers::try_from(header_block).map_err(|_| ProxyError::InvalidResponse)?;
+
+        // Interim responses such as 103 Early Hints aren't passed on.
+        if status_code.is_informational() {
+            buffer.drain(..head_len);
+            continue;
+        }
+
+        let bodiless = head_only || matches!(status_code, StatusCode::NoContent | StatusCode::NotModified);
+        let mut response = Response::new(status_code, None);
+        for (name, value) in headers.iter() {
+            // Without a body to measure, the upstream's length is the only one.
+            if !is_hop_by_hop(name, &headers) && (bodiless || !name.eq_ignore_ascii_case("Content-Length")) {
+                response.headers_mut().append(name.to_string(), value.to_string());
+            }
+        }
+        let length = BodyLength::from_headers(&headers).map_err(|_| ProxyError::InvalidResponse)?;
+
+        let body = match length {
+            _ if bodiless => Vec::new(),
+            BodyLength::Fixed(len) if len > max_response_size => return Err(ProxyError::ResponseTooLarge),
+            BodyLength::Fixed(len) => {
+                while buffer.len() < head_len + len {
+                    if fill(stream, &mut buffer, limit)? {
+                        return Err(ProxyError::InvalidResponse);
+                    }
+                }
+                buffer[head_len..head_len + len].to_vec()
+            }
+            BodyLength::Chunked => loop {
+                match body::decode_chunked(&buffer[head_len..], max_response_size) {
+                    Ok(Some((body, _))) => break body,
+                    Ok(None) => {
+                        if fill(stream, &mut buffer, limit)? {
+                            return Err(ProxyError::InvalidResponse);
+                        }
+                    }
+                    Err(_) => return Err(ProxyError::InvalidResponse),
+                }
+            },
+            // Without a length, the body runs until the upstream closes.
+            BodyLength::Empty => {
+                while !fill(stream, &mut buffer, limit)? {}
+                buffer[head_len..].to_vec()
+            }
+        };
+
+        // Even an empty body is the upstream's, not a cue for our error page.
+        response.set_body(body);
+        return Ok(response);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// An upstream that answers one request with `reply` and hands back
+    /// what it received.
+    fn upstream(reply: &'static [u8]) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let received = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buffer = Vec::new();
+            while Request::head_len(&buffer).is_none() {
+                let mut chunk = [0; 1024];
+                let n = stream.read(&mut chunk).unwrap();
+                buffer.extend_from_slice(&chunk[..n]);
+            }
+            stream.write_all(reply).unwrap();
+            String::from_utf8(buffer).unwrap()
+        });
+        (addr, received)
+    }
+
+    #[test]
+    fn forwards_with_rewritten_headers() {
+        let (addr, received) =
+            upstream(b"HTTP/1.1 201 Created\r\nKeep-Alive: timeout=5\r\nX-Upstream: yes\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nok\r\n0\r\n\r\n");
+        let proxy = ProxyHandler::new().route("/api", Upstreams::new([addr.clone()]));
+        let raw = b"GET /api/items?page=2 HTTP/1.1\r\nHost: example.com\r\nConnection: keep-alive, X-Secret\r\nX-Secret: 1\r\nX-Forwarded-For: 10.0.0.1\r\n\r\n";
+        let request = Request::try_from(&raw[..]).unwrap().with_remote_addr(Some([192, 0, 2, 7].into()));
+
+        let response = proxy.handle_request(&request);
+        let sent = received.join().unwrap();
+
+        assert!(sent.starts_with(&format!("GET /api/items?page=2 HTTP/1.1\r\nHost: {addr}\r\n")));
+        assert!(sent.contains("X-Forwarded-For: 10.0.0.1, 192.0.2.7\r\n"));
+        assert!(sent.contains("X-Forwarded-Proto: http\r\nX-Forwarded-Host: example.com\r\n"));
+        assert!(!sent.contains("X-Secret") && !sent.contains("keep-alive"));
+        assert_eq!(response.status_code(), StatusCode::Created);
+        assert_eq!(response.headers().get("X-Upstream"), Some("yes"));
+        assert!(!response.headers().contains("Keep-Alive") && !response.headers().contains("Transfer-Encoding"));
+        assert_eq!(response.body().and_then(|body| body.as_bytes()), Some(&b"ok"[..]));
+    }
+
+    #[test]
+    fn maps_failures_and_skips_unhealthy_upstreams() {
+        // Bound then dropped, so nothing listens there.
+        let dead = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = ProxyHandler::new()
+            .route("/dead", Upstreams::new([dead]))
+            .route("/slow", Upstreams::new([silent.local_addr().unwrap().to_string()]))
+            .with_timeouts(Duration::from_secs(1), Duration::from_millis(100))
+            .with_health_check(1, Duration::from_secs(60));
+        let get = |path: &str| {
+            let raw = format!("GET {path} HTTP/1.1\r\n\r\n");
+            proxy.handle_request(&Request::try_from(raw.as_bytes()).unwrap()).status_code()
+        };
+
+        assert_eq!(get("/dead"), StatusCode::BadGateway);
+        assert_eq!(get("/slow/x"), StatusCode::GatewayTimeout);
+        assert!(proxy.routes.iter().all(|route| route.upstreams.candidates().is_empty()));
+        assert_eq!(get("/elsewhere"), StatusCode::NotFound);
+    }
+
+    #[test]
+    fn bodiless_responses_keep_the_upstream_length() {
+        let (head_addr, _) = upstream(b"HTTP/1.1 200 OK\r\nContent-Length: 1234\r\n\r\n");
+        let (cached_addr, _) = upstream(b"HTTP/1.1 304 Not Modified\r\nContent-Length: 99\r\nETag: \"v1\"\r\n\r\n");
+        let proxy = ProxyHandler::new()
+            .route("/head", Upstreams::new([head_addr]))
+            .route("/cached", Upstreams::new([cached_addr]));
+        let sent = |raw: &str, head_only: bool| {
+            let response = proxy.handle_request(&Request::try_from(raw.as_bytes()).unwrap());
+            let mut sent = Vec::new();
+            match head_only {
+                true => response.send_head(&mut sent).unwrap(),
+                false => response.send(&mut sent).unwrap(),
+            }
+            String::from_utf8(sent).unwrap()
+        };
+
+        assert_eq!(sent("HEAD /head HTTP/1.1\r\n\r\n", true), "HTTP/1.1 200 OK\r\nContent-Length: 1234\r\n\r\n");
+        let cached = sent("GET /cached HTTP/1.1\r\n\r\n", false);
+        assert!(cached.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(cached.ends_with("Content-Length: 99\r\n\r\n"));
+        assert_eq!(cached.matches("Content-Length").count(), 1);
+    }
+
+    #[test]
+    fn candidates_take_turns_or_favour_the_least_busy() {
+        let addrs = |upstreams: &Upstreams| -> Vec<String> {
+            upstreams.candidates().iter().map(|upstream| upstream.addr.clone()).collect()
+        };
+
+        let round_robin = Upstreams::new(["a", "b", "c"]);
+        assert_eq!(addrs(&round_robin), ["a", "b", "c"]);
+        assert_eq!(addrs(&round_robin), ["b", "c", "a"]);
+        assert_eq!(addrs(&round_robin), ["c", "a", "b"]);
+        assert_eq!(addrs(&round_robin), ["a", "b", "c"]);
+
+        let least = Upstreams::new(["a", "b", "c"]).with_balance(Balance::LeastConnections);
+        least.servers[0].active.store(1, Ordering::SeqCst);
+        least.servers[1].active.store(2, Ordering::SeqCst);
+        assert_eq!(addrs(&least), ["c", "a", "b"]);
+        least.servers[1].active.store(0, Ordering::SeqCst);
+        // Ties between idle upstreams still rotate.
+        assert_eq!(addrs(&least), ["b", "c", "a"]);
+        assert_eq!(addrs(&least), ["c", "b", "a"]);
+    }
+}
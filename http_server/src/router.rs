@@ -0,0 +1,230 @@
+use super::http::{Method, Request, Response, StatusCode};
+use super::server::Handler;
+
+/// Dispatches requests to handlers registered by method and path pattern.
+///
+/// Patterns are split on `/`. A segment written `:name` captures one path
+/// segment, and a final `*name` captures whatever is left of the path.
+/// Captured values are available from `Request::param` in the handler.
+///
+/// ```ignore
+/// let router = Router::new()
+///     .get("/users/:id", |request: &Request| { ... })
+///     .get("/static/*rest", WebsiteHandler::new(public_path))
+///     .mount("/api", api_router);
+/// ```
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Option<Box<dyn Handler>>,
+}
+
+struct Route {
+    method: Method,
+    pattern: Vec<Segment>,
+    handler: Box<dyn Handler>,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Rest(String),
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            fallback: None,
+        }
+    }
+
+    pub fn route(mut self, method: Method, pattern: &str, handler: impl Handler + 'static) -> Self {
+        self.routes.push(Route {
+            method,
+            pattern: parse_pattern(pattern),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get(self, pattern: &str, handler: impl Handler + 'static) -> Self {
+        self.route(Method::GET, pattern, handler)
+    }
+
+    pub fn post(self, pattern: &str, handler: impl Handler + 'static) -> Self {
+        self.route(Method::POST, pattern, handler)
+    }
+
+    pub fn put(self, pattern: &str, handler: impl Handler + 'static) -> Self {
+        self.route(Method::PUT, pattern, handler)
+    }
+
+    pub fn delete(self, pattern: &str, handler: impl Handler + 'static) -> Self {
+        self.route(Method::DELETE, pattern, handler)
+    }
+
+    /// Serves every route of `router` under `prefix`. The sub-router's
+    /// fallback, if any, is dropped.
+    pub fn mount(mut self, prefix: &str, router: Router) -> Self {
+        let prefix = parse_pattern(prefix);
+        for mut route in router.routes {
+            let mut pattern = prefix.clone();
+            pattern.append(&mut route.pattern);
+            route.pattern = pattern;
+            self.routes.push(route);
+        }
+        self
+    }
+
+    /// Handles requests that match no route. Without one they get a 404.
+    pub fn fallback(mut self, handler: impl Handler + 'static) -> Self {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Handler for Router {
+    fn handle_request(&self, request: &Request) -> Response {
+        let mut allowed: Vec<Method> = Vec::new();
+
+        for route in &self.routes {
+            let Some(params) = match_path(&route.pattern, request.path()) else {
+                continue;
+            };
+            if route.method == *request.method() {
+                let request = request.clone().with_params(params);
+                return route.handler.handle_request(&request);
+            }
+            if !allowed.contains(&route.method) {
+                allowed.push(route.method);
+            }
+        }
+
+        if !allowed.is_empty() {
+            let allow: Vec<&str> = allowed.iter().map(Method::as_str).collect();
+            return Response::builder()
+                .status(StatusCode::MethodNotAllowed)
+                .header("Allow", allow.join(", "))
+                .build();
+        }
+
+        match &self.fallback {
+            Some(fallback) => fallback.handle_request(request),
+            None => Response::new(StatusCode::NotFound, None),
+        }
+    }
+}
+
+/// Lets plain functions and closures be used as handlers.
+impl<F> Handler for F
+where
+    F: Fn(&Request) -> Response + Send + Sync,
+{
+    fn handle_request(&self, request: &Request) -> Response {
+        self(request)
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    pattern
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| {
+            if let Some(name) = segment.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = segment.strip_prefix('*') {
+                Segment::Rest(name.to_string())
+            } else {
+                Segment::Literal(segment.to_string())
+            }
+        })
+        .collect()
+}
+
+/// Matches `path` against `pattern`, returning the captured parameters.
+fn match_path(pattern: &[Segment], path: &str) -> Option<Vec<(String, String)>> {
+    let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+    let mut params = Vec::new();
+
+    for expected in pattern {
+        match expected {
+            Segment::Literal(literal) => {
+                if segments.next()? != literal {
+                    return None;
+                }
+            }
+            Segment::Param(name) => params.push((name.clone(), segments.next()?.to_string())),
+            Segment::Rest(name) => {
+                let rest: Vec<&str> = segments.by_ref().collect();
+                params.push((name.clone(), rest.join("/")));
+            }
+        }
+    }
+
+    match segments.next() {
+        Some(_) => None,
+        None => Some(params),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::ResponseBody;
+
+    fn echo_param(request: &Request) -> Response {
+        let id: u32 = request.param_as("id").unwrap();
+        Response::new(StatusCode::OK, Some(format!("user {id}").into_bytes()))
+    }
+
+    fn send(router: &Router, raw: &str) -> Response {
+        let request = Request::try_from(raw.as_bytes()).unwrap();
+        router.handle_request(&request)
+    }
+
+    #[test]
+    fn captures_params_and_rest() {
+        let router = Router::new()
+            .get("/users/:id", echo_param)
+            .get("/static/*rest", |request: &Request| {
+                Response::new(StatusCode::OK, Some(request.param("rest").unwrap().into()))
+            });
+
+        let user = send(&router, "GET /users/42 HTTP/1.1\r\n\r\n");
+        let file = send(&router, "GET /static/css/site.css HTTP/1.1\r\n\r\n");
+
+        assert_eq!(user.body().and_then(ResponseBody::as_bytes), Some(&b"user 42"[..]));
+        assert_eq!(file.body().and_then(ResponseBody::as_bytes), Some(&b"css/site.css"[..]));
+    }
+
+    #[test]
+    fn reports_allowed_methods_on_mismatch() {
+        let router = Router::new()
+            .get("/users/:id", echo_param)
+            .delete("/users/:id", echo_param);
+
+        let response = send(&router, "POST /users/1 HTTP/1.1\r\n\r\n");
+        let missing = send(&router, "GET /nope HTTP/1.1\r\n\r\n");
+
+        assert_eq!(response.status_code() as u16, 405);
+        assert_eq!(response.headers().get("Allow"), Some("GET, DELETE"));
+        assert_eq!(missing.status_code() as u16, 404);
+    }
+
+    #[test]
+    fn mounts_sub_routers_under_a_prefix() {
+        let api = Router::new().get("/users/:id", echo_param);
+        let router = Router::new().mount("/api/v1", api);
+
+        let response = send(&router, "GET /api/v1/users/7 HTTP/1.1\r\n\r\n");
+
+        assert_eq!(response.body().and_then(ResponseBody::as_bytes), Some(&b"user 7"[..]));
+    }
+}
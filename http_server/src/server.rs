@@ -1,73 +1,581 @@
-use crate::http::{Request, Response, StatusCode, ParseError};
+use crate::http::{compression, Method, Request, Response, ResponseBody, StatusCode, ParseError, Transport};
 use std::convert::TryFrom;
 use std::convert::TryInto;
-use std::io::{Read, Write};
-use std::net::TcpListener;
+use std::io::{ErrorKind, Read, Write};
+use std::collections::HashMap;
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+use crate::access_log::{AccessLog, Entry, RequestInfo};
+use crate::shutdown::{ConnectionGuard, ShutdownHandle};
+use crate::thread_pool::ThreadPool;
+use crate::tls::{HttpsRedirect, TlsConfig};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
 
-pub trait Handler {
-    fn handle_request(&mut self, request: &Request) -> Response;
-    fn handle_bad_request(&mut self, e: &ParseError) -> Response {
+/// Largest request body accepted unless configured otherwise.
+pub(crate) const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+/// Largest request line plus header block buffered by default.
+const DEFAULT_MAX_HEADER_SIZE: usize = 8 * 1024;
+/// Most header lines accepted in one request by default.
+const DEFAULT_MAX_HEADERS: usize = 100;
+/// How long an idle connection is held open by default.
+pub(crate) const DEFAULT_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a client gets by default to finish sending a request's head
+/// once it has started.
+pub(crate) const DEFAULT_HEADER_READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a client gets by default to send a request's body once the
+/// head is in.
+pub(crate) const DEFAULT_BODY_READ_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a single write to a client may block by default.
+pub(crate) const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+/// How many connections one IP address may hold open at once by default.
+const DEFAULT_MAX_CONNECTIONS_PER_IP: usize = 16;
+/// How many requests one connection may make before it is closed by default.
+const DEFAULT_MAX_REQUESTS_PER_CONNECTION: usize = 100;
+/// How many connections are served at once by default. Workers spend most
+/// of their time blocked on sockets, so this isn't tied to the CPU count.
+pub(crate) const DEFAULT_WORKERS: usize = 8;
+/// How many accepted connections may wait for a free worker by default.
+pub(crate) const DEFAULT_QUEUE_SIZE: usize = 64;
+/// How long in-flight requests get to finish on shutdown by default.
+pub(crate) const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+/// At this verbosity and above, raw requests are printed as they arrive.
+const VERBOSITY_DEBUG: u8 = 2;
+
+/// Handlers are shared by every worker thread, so they take `&self` and
+/// must be safe to use from several threads at once.
+pub trait Handler: Send + Sync {
+    fn handle_request(&self, request: &Request) -> Response;
+    fn handle_bad_request(&self, e: &ParseError) -> Response {
         println!("Failed to parse request: {e}");
-        Response::new(StatusCode::BadRequest, None)
+        Response::error(e.status_code())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Server {
     addr: String,
+    max_body_size: usize,
+    max_header_size: usize,
+    max_headers: usize,
+    keep_alive_timeout: Duration,
+    header_read_timeout: Duration,
+    body_read_timeout: Duration,
+    write_timeout: Duration,
+    max_connections_per_ip: usize,
+    connections_per_ip: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    max_requests_per_connection: usize,
+    workers: usize,
+    queue_size: usize,
+    compression: bool,
+    tls: Option<TlsConfig>,
+    https_redirect_addrs: Vec<String>,
+    access_log: Option<Arc<AccessLog>>,
+    verbosity: u8,
+    shutdown: ShutdownHandle,
+    shutdown_timeout: Duration,
+}
+
+enum ReadError {
+    Io(std::io::Error),
+    Parse(ParseError),
+    /// The client started a request but didn't finish it in time.
+    Timeout,
+}
+
+/// Counts a connection against its IP address's limit while it is alive.
+struct IpSlot {
+    connections_per_ip: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    ip: IpAddr,
+}
+
+impl Drop for IpSlot {
+    fn drop(&mut self) {
+        let mut connections = self.connections_per_ip.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = connections.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.ip);
+            }
+        }
+    }
 }
 
 fn arr(a: &[u8]) {}
 
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+/// Turns away a connection over its address's limit without letting a
+/// client that doesn't read hold up the accept loop.
+fn reject(mut stream: TcpStream) {
+    let mut response = Response::error(StatusCode::TooManyRequests).with_error_page();
+    response.headers_mut().insert("Connection", "close");
+    if stream.set_nonblocking(true).is_ok() {
+        let _ = response.send(&mut stream);
+    }
+}
+
 impl Server {
     pub fn new(addr: String) -> Self {
-        Self { addr }
+        Self {
+            addr,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_header_size: DEFAULT_MAX_HEADER_SIZE,
+            max_headers: DEFAULT_MAX_HEADERS,
+            keep_alive_timeout: DEFAULT_KEEP_ALIVE_TIMEOUT,
+            header_read_timeout: DEFAULT_HEADER_READ_TIMEOUT,
+            body_read_timeout: DEFAULT_BODY_READ_TIMEOUT,
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+            max_connections_per_ip: DEFAULT_MAX_CONNECTIONS_PER_IP,
+            connections_per_ip: Arc::default(),
+            max_requests_per_connection: DEFAULT_MAX_REQUESTS_PER_CONNECTION,
+            workers: DEFAULT_WORKERS,
+            queue_size: DEFAULT_QUEUE_SIZE,
+            compression: true,
+            tls: None,
+            https_redirect_addrs: Vec::new(),
+            access_log: None,
+            verbosity: 1,
+            shutdown: ShutdownHandle::default(),
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+        }
     }
 
-    pub fn run(&mut self, mut handler: impl Handler) {
-        println!("Listening on {}", self.addr);
+    /// Moves the server to another address before it runs.
+    #[cfg(test)]
+    pub(crate) fn with_addr(mut self, addr: String) -> Self {
+        self.addr = addr;
+        self
+    }
 
-        let listener = TcpListener::bind(&self.addr).unwrap();
+    /// Requests whose body is larger than `max_body_size` bytes are
+    /// answered with 413 Payload Too Large.
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Requests whose line and headers together are longer than
+    /// `max_header_size` bytes, or that have more than `max_headers` header
+    /// lines, are answered with 431 Request Header Fields Too Large.
+    pub fn with_header_limits(mut self, max_header_size: usize, max_headers: usize) -> Self {
+        self.max_header_size = max_header_size;
+        self.max_headers = max_headers;
+        self
+    }
+
+    /// Idle connections, whether new or between keep-alive requests, are
+    /// closed after `timeout` without the start of a request.
+    pub fn with_keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.keep_alive_timeout = timeout;
+        self
+    }
+
+    /// Once a request has started, its head must arrive within
+    /// `header_timeout` and then its body within `body_timeout`, or it is
+    /// answered with 408 Request Timeout. These are deadlines, so trickling
+    /// in a byte at a time doesn't extend them.
+    pub fn with_read_timeouts(mut self, header_timeout: Duration, body_timeout: Duration) -> Self {
+        self.header_read_timeout = header_timeout;
+        self.body_read_timeout = body_timeout;
+        self
+    }
+
+    /// Connections whose client stops reading for `timeout` are dropped.
+    pub fn with_write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = timeout;
+        self
+    }
+
+    /// Connections beyond `max` open at once from the same IP address are
+    /// answered with 429 Too Many Requests and closed. 0 means no limit.
+    pub fn with_max_connections_per_ip(mut self, max: usize) -> Self {
+        self.max_connections_per_ip = max;
+        self
+    }
+
+    /// Connections are closed after serving `max` requests; pass 1 to turn
+    /// keep-alive off.
+    pub fn with_max_requests_per_connection(mut self, max: usize) -> Self {
+        self.max_requests_per_connection = max.max(1);
+        self
+    }
+
+    /// Connections are served by `workers` threads.
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Once `queue_size` accepted connections are waiting for a worker, the
+    /// server stops accepting until one frees up.
+    pub fn with_queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = queue_size;
+        self
+    }
+
+    /// Whether response bodies are compressed for clients that accept it.
+    /// On by default.
+    pub fn with_compression(mut self, compression: bool) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Serves HTTPS instead of plain HTTP, completing the TLS handshake
+    /// before any request is read.
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Also listens for plain HTTP on `addr` and redirects every request
+    /// there to the same URL over HTTPS. Only used together with `with_tls`,
+    /// and can be called again to redirect from several addresses.
+    pub fn with_https_redirect(mut self, addr: String) -> Self {
+        self.https_redirect_addrs.push(addr);
+        self
+    }
+
+    /// Writes a line per request to `access_log`, which several servers
+    /// may share. Off by default.
+    pub fn with_access_log(mut self, access_log: impl Into<Arc<AccessLog>>) -> Self {
+        self.access_log = Some(access_log.into());
+        self
+    }
+
+    /// 0 prints only errors, 1 (the default) also says where the server
+    /// is listening, and 2 dumps the start of every raw request.
+    pub fn with_verbosity(mut self, verbosity: u8) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    /// On shutdown, requests still in flight after `timeout` have their
+    /// connections closed under them.
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// A handle that makes `run` stop accepting, drain and return.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Stops this server along with every other one given `shutdown`.
+    pub fn with_shutdown_handle(mut self, shutdown: ShutdownHandle) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Reads from `stream` until `buffer` holds one complete request, or the
+    /// peer stops sending. Returns the length of the request in `buffer`.
+    ///
+    /// Each stage runs against its own deadline: the idle wait for a request
+    /// to start, then its head, then its body.
+    fn read_request(&self, stream: &mut impl Transport, buffer: &mut Vec<u8>) -> Result<usize, ReadError> {
+        let mut chunk = [0; 1024];
+        let mut stage = None;
+        let mut deadline = Instant::now();
+
+        loop {
+            let head_len = Request::head_len(buffer);
+            let head_too_large = match head_len {
+                None => buffer.len() > self.max_header_size,
+                // Less the request line and the blank line ending the head.
+                Some(len) => {
+                    len > self.max_header_size
+                        || buffer[..len].iter().filter(|&&b| b == b'\n').count() > self.max_headers + 2
+                }
+            };
+            if head_too_large {
+                return Err(ReadError::Parse(ParseError::HeadersTooLarge));
+            }
+            match Request::message_len(buffer, self.max_body_size) {
+                Ok(Some(len)) => return Ok(len),
+                Ok(None) => {}
+                Err(e) => return Err(ReadError::Parse(e)),
+            }
+
+            let (current, timeout) = match (buffer.is_empty(), head_len) {
+                (true, _) => (0, self.keep_alive_timeout),
+                (false, None) => (1, self.header_read_timeout),
+                (false, Some(_)) => (2, self.body_read_timeout),
+            };
+            if stage != Some(current) {
+                stage = Some(current);
+                deadline = Instant::now() + timeout;
+            }
+            let timed_out = || match buffer.is_empty() {
+                true => ReadError::Io(ErrorKind::TimedOut.into()),
+                false => ReadError::Timeout,
+            };
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(timed_out());
+            }
+            stream.socket().set_read_timeout(Some(remaining)).map_err(ReadError::Io)?;
+
+            let bytes_read = match stream.read(&mut chunk) {
+                Ok(bytes_read) => bytes_read,
+                Err(e) if is_timeout(&e) => return Err(timed_out()),
+                Err(e) => return Err(ReadError::Io(e)),
+            };
+            if bytes_read == 0 {
+                // The peer is done sending; let the parser judge what arrived.
+                return Ok(buffer.len());
+            }
+            buffer.extend_from_slice(&chunk[..bytes_read]);
+        }
+    }
+
+    /// Counts a new connection from `ip`, or returns `None` if that address
+    /// already has as many open as it may.
+    fn admit(&self, ip: IpAddr) -> Option<IpSlot> {
+        let mut connections = self.connections_per_ip.lock().unwrap_or_else(|e| e.into_inner());
+        let count = connections.entry(ip).or_default();
+        if self.max_connections_per_ip > 0 && *count >= self.max_connections_per_ip {
+            return None;
+        }
+        *count += 1;
+        Some(IpSlot {
+            connections_per_ip: Arc::clone(&self.connections_per_ip),
+            ip,
+        })
+    }
+
+    /// Sets up one accepted connection, shaking hands first when `tls` is
+    /// given, and then serves it.
+    fn handle_connection(
+        &self,
+        mut stream: TcpStream,
+        guard: Option<ConnectionGuard>,
+        tls: Option<&Arc<ServerConfig>>,
+        handler: &impl Handler,
+    ) {
+        let configured = stream
+            .set_read_timeout(Some(self.header_read_timeout))
+            .and_then(|_| stream.set_write_timeout(Some(self.write_timeout)));
+        if let Err(e) = configured {
+            println!("Failed to configure connection: {e}");
+            return;
+        }
+
+        let remote_addr = stream.peer_addr().ok().map(|addr| addr.ip());
+        let Some(tls) = tls else {
+            return self.serve(&mut stream, remote_addr, guard.as_ref(), handler);
+        };
+        let conn = match ServerConnection::new(Arc::clone(tls)) {
+            Ok(conn) => conn,
+            Err(e) => {
+                println!("Failed to start TLS session: {e}");
+                return;
+            }
+        };
+        let mut stream = StreamOwned::new(conn, stream);
+        while stream.conn.is_handshaking() {
+            if let Err(e) = stream.conn.complete_io(&mut stream.sock) {
+                println!("TLS handshake failed: {e}");
+                return;
+            }
+        }
+
+        self.serve(&mut stream, remote_addr, guard.as_ref(), handler);
+        stream.conn.send_close_notify();
+        let _ = stream.flush();
+    }
+
+    /// Serves requests on one connection until the client asks to close,
+    /// goes quiet for longer than the keep-alive timeout, or uses up its
+    /// request allowance, or the server shuts down. Pipelined requests are
+    /// answered in order.
+    fn serve(
+        &self,
+        stream: &mut impl Transport,
+        remote_addr: Option<IpAddr>,
+        guard: Option<&ConnectionGuard>,
+        handler: &impl Handler,
+    ) {
+        let mut buffer = Vec::new();
+        let mut served = 0;
 
         loop {
+            // Between requests the connection can be closed at shutdown
+            // without cutting anyone off.
+            let idle = buffer.is_empty();
+            if idle && let Some(guard) = guard {
+                guard.set_idle(true);
+            }
+            let read = self.read_request(stream, &mut buffer);
+            if idle && let Some(guard) = guard {
+                guard.set_idle(false);
+            }
+            let (started, time) = (Instant::now(), SystemTime::now());
+            let mut request_info = RequestInfo::default();
+
+            let (response, keep_alive, head_only) = match read {
+                Ok(0) => return,
+                Ok(len) => {
+                    if self.verbosity >= VERBOSITY_DEBUG {
+                        let request_str = String::from_utf8_lossy(&buffer[..len]);
+                        println!("Received {} bytes", len);
+                        println!("Raw request: {:?}", request_str.chars().take(200).collect::<String>());
+                    }
+                    let result = match Request::try_from(&buffer[..len]) {
+                        Ok(request) => {
+                            let request = request.with_remote_addr(remote_addr).with_secure(self.tls.is_some());
+                            if self.access_log.is_some() {
+                                request_info = RequestInfo::from_request(&request);
+                            }
+                            let head_only = *request.method() == Method::HEAD;
+                            let mut response = handler.handle_request(&request);
+                            if self.compression {
+                                response = compression::compress_response(request.header("Accept-Encoding"), response, head_only);
+                            }
+                            (response, request.keep_alive(), head_only)
+                        }
+                        Err(e) => (handler.handle_bad_request(&e), false, false),
+                    };
+                    // Whatever follows is the start of the next pipelined request.
+                    buffer.drain(..len);
+                    result
+                }
+                // After a framing error we can't tell where the next request starts.
+                Err(ReadError::Parse(e)) => (handler.handle_bad_request(&e), false, false),
+                Err(ReadError::Timeout) => (Response::error(StatusCode::RequestTimeout), false, false),
+                Err(ReadError::Io(e)) if is_timeout(&e) => return,
+                Err(ReadError::Io(e)) => {
+                    println!("Failed to read from connection: {e}");
+                    return;
+                }
+            };
+
+            // Handlers can answer with a bare error status and leave the page
+            // to us.
+            let mut response = response.with_error_page();
+            let upgrade = match response.status_code() {
+                StatusCode::SwitchingProtocols => response.take_upgrade(),
+                _ => None,
+            };
+
+            // Only closing the connection ends a streamed body.
+            let streamed = !head_only && response.body().is_some_and(ResponseBody::is_stream);
+
+            served += 1;
+            let keep_alive = keep_alive
+                && !streamed
+                && served < self.max_requests_per_connection
+                && !response.headers().contains_token("Connection", "close")
+                && !self.shutdown.is_shutting_down();
+            if !keep_alive && upgrade.is_none() {
+                response.headers_mut().insert("Connection", "close");
+            }
+
+            let sent = match head_only {
+                true => response.send_head(stream),
+                false => response.send(stream),
+            };
+            if let Err(e) = sent {
+                println!("Failed to send response: {}", e);
+                return;
+            }
+
+            if let Some(access_log) = &self.access_log {
+                let bytes = match head_only {
+                    true => 0,
+                    false => response.body().map_or(0, ResponseBody::len),
+                };
+                access_log.log(&Entry {
+                    remote_addr,
+                    time,
+                    request: request_info,
+                    status: response.status_code(),
+                    bytes,
+                    duration: started.elapsed(),
+                });
+            }
+
+            // The connection now speaks another protocol, which shutting down
+            // may interrupt at any time.
+            if let Some(upgrade) = upgrade {
+                if let Some(guard) = guard {
+                    guard.set_idle(true);
+                }
+                return upgrade.run(stream);
+            }
+            if streamed && let Some(ResponseBody::Stream(body)) = response.body() {
+                if let Some(guard) = guard {
+                    guard.set_idle(true);
+                }
+                if let Err(e) = body.run(stream) {
+                    println!("Failed to stream response: {}", e);
+                }
+                return;
+            }
+            if !keep_alive {
+                return;
+            }
+        }
+    }
+
+    /// Serves connections until the shutdown handle is used, then waits
+    /// for in-flight requests and returns. Returns straight away, after
+    /// saying why, if the address can't be listened on or the TLS setup
+    /// fails.
+    pub fn run(&mut self, handler: impl Handler + 'static) {
+        if self.verbosity > 0 {
+            println!("Listening on {} with {} workers", self.addr, self.workers);
+        }
+
+        let tls = match self.tls.as_ref().map(TlsConfig::server_config).transpose() {
+            Ok(tls) => tls,
+            Err(e) => {
+                println!("Failed to set up TLS for {}: {e}", self.addr);
+                return;
+            }
+        };
+        let listener = match TcpListener::bind(&self.addr) {
+            Ok(listener) => listener,
+            Err(e) => {
+                println!("Failed to listen on {}: {e}", self.addr);
+                return;
+            }
+        };
+        if let Ok(addr) = listener.local_addr() {
+            self.shutdown.add_listener(addr);
+        }
+        let pool = ThreadPool::new(self.workers, self.queue_size);
+        let server = Arc::new(self.clone());
+        let handler = Arc::new(handler);
+
+        let redirects: Vec<_> = match &tls {
+            Some(_) => self.https_redirect_addrs.iter().filter_map(|addr| self.run_https_redirect(addr)).collect(),
+            None => Vec::new(),
+        };
+
+        while !self.shutdown.is_shutting_down() {
             match listener.accept() {
-                Ok((mut stream, _)) => {
+                // The connection that woke us up to shut down isn't served.
+                Ok(_) if self.shutdown.is_shutting_down() => break,
+                Ok((stream, addr)) => {
                     //let a = [1,2,3,4, 5,7 ];
                     //arr(&a[1..3]);
 
-                    let mut buffer = [0; 1024];
-
-                    match stream.read(&mut buffer) {
-                        Ok(bytes_read) => {
-                            let request_str = String::from_utf8_lossy(&buffer[..bytes_read]);
-                            println!("Received {} bytes", bytes_read);
-                            println!("Raw request: {:?}", &request_str[..request_str.chars().count().min(200)]);
-                            let response = match Request::try_from(&buffer[..bytes_read]) {
-                                Ok(request) => {
-                                    //dbg!(request);
-                                    //let response = Response::new(StatusCode::NotFound, None);
-                                    handler.handle_request(&request)
-
-                                    /*
-                                    Response::new(
-                                        StatusCode::OK,
-                                        Some("<h1>It works!</h1>".to_string()),
-                                    )
-                                    */
-                                }
-                                Err(e) => {
-                                    //println!("Failed to parse a request: {e}");
-                                    //Response::new(StatusCode::BadRequest, None)
-
-                                    handler.handle_bad_request(&e)
-                                }
-                            };
-                            if let Err(e) = response.send(&mut stream) {
-                                println!("Failed to send response: {}", e);
-                            }
-                        }
-                        Err(e) => println!("Failed to read from connection: {e}"),
-                    }
+                    let Some(slot) = self.admit(addr.ip()) else {
+                        reject(stream);
+                        continue;
+                    };
+                    let guard = self.shutdown.track(&stream);
+                    let server = Arc::clone(&server);
+                    let handler = Arc::clone(&handler);
+                    let tls = tls.clone();
+                    pool.execute(move || {
+                        server.handle_connection(stream, guard, tls.as_ref(), handler.as_ref());
+                        drop(slot);
+                    });
                 }
                 Err(e) => {
                     println!("Failed to establish a connection: {e:?}");
@@ -84,5 +592,207 @@ impl Server {
             let (stream, addr) = res.unwrap();
             */
         }
+
+        drop(listener);
+        if !self.shutdown.wait_for_connections(self.shutdown_timeout) {
+            println!("Closed connections still busy after {:?}", self.shutdown_timeout);
+        }
+        drop(pool);
+        for redirect in redirects {
+            let _ = redirect.join();
+        }
+    }
+
+    /// Answers plain HTTP on `addr` with redirects to this server, on a
+    /// thread of its own that stops along with the server. Returns `None`,
+    /// after saying why, if `addr` can't be listened on.
+    fn run_https_redirect(&self, addr: &str) -> Option<JoinHandle<()>> {
+        if self.verbosity > 0 {
+            println!("Redirecting http://{addr} to HTTPS");
+        }
+
+        let listener = match TcpListener::bind(addr) {
+            Ok(listener) => listener,
+            Err(e) => {
+                println!("Failed to listen on {addr}: {e}");
+                return None;
+            }
+        };
+        if let Ok(addr) = listener.local_addr() {
+            self.shutdown.add_listener(addr);
+        }
+        let https_port = self.addr.rsplit_once(':').and_then(|(_, port)| port.parse().ok());
+        let redirect = Arc::new(HttpsRedirect::new(https_port));
+        // Nothing worth keeping a connection open for.
+        let server = Arc::new(Server {
+            max_requests_per_connection: 1,
+            ..self.clone()
+        });
+
+        Some(thread::spawn(move || {
+            let pool = ThreadPool::new(2, server.queue_size);
+            while !server.shutdown.is_shutting_down() {
+                match listener.accept() {
+                    Ok(_) if server.shutdown.is_shutting_down() => break,
+                    Ok((stream, addr)) => {
+                        let Some(slot) = server.admit(addr.ip()) else {
+                            reject(stream);
+                            continue;
+                        };
+                        let guard = server.shutdown.track(&stream);
+                        let server = Arc::clone(&server);
+                        let redirect = Arc::clone(&redirect);
+                        pool.execute(move || {
+                            server.handle_connection(stream, guard, None, redirect.as_ref());
+                            drop(slot);
+                        });
+                    }
+                    Err(e) => println!("Failed to establish a connection: {e:?}"),
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::start;
+    use std::net::TcpStream;
+
+    fn ok(_: &Request) -> Response {
+        Response::new(StatusCode::OK, Some(b"done".to_vec()))
+    }
+
+    fn read_all(mut stream: TcpStream) -> String {
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn shutdown_finishes_in_flight_requests_then_returns() {
+        let (addr, shutdown, running) = start(Server::new(String::new()), |request: &Request| {
+            thread::sleep(Duration::from_millis(300));
+            ok(request)
+        });
+
+        let mut stream = TcpStream::connect(&addr).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+        thread::sleep(Duration::from_millis(100));
+        shutdown.shutdown();
+
+        let response = read_all(stream);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("done"));
+
+        running.join().unwrap();
+        assert!(TcpStream::connect(&addr).is_err());
+    }
+
+    fn path(request: &Request) -> Response {
+        Response::new(StatusCode::OK, Some(request.path().as_bytes().to_vec()))
+    }
+
+    #[test]
+    fn answers_pipelined_requests_in_order() {
+        let (addr, shutdown, running) = start(Server::new(String::new()), path);
+
+        let mut stream = TcpStream::connect(&addr).unwrap();
+        stream.write_all(b"GET /first HTTP/1.1\r\n\r\nGET /second HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+
+        let response = read_all(stream);
+        let first = response.find("/first").unwrap();
+        let second = response.find("/second").unwrap();
+        assert!(first < second);
+        assert_eq!(response.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+        assert!(response.ends_with("Connection: close\r\nContent-Length: 7\r\n\r\n/second"));
+        shutdown.shutdown();
+        running.join().unwrap();
+    }
+
+    #[test]
+    fn connection_close_ends_the_connection_after_one_response() {
+        let (addr, shutdown, running) = start(Server::new(String::new()), path);
+
+        let mut stream = TcpStream::connect(&addr).unwrap();
+        stream.write_all(b"GET /first HTTP/1.1\r\nConnection: close\r\n\r\nGET /second HTTP/1.1\r\n\r\n").unwrap();
+
+        let response = read_all(stream);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("/first"));
+        shutdown.shutdown();
+        running.join().unwrap();
+    }
+
+    #[test]
+    fn run_returns_when_the_address_is_taken() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut server = Server::new(taken.local_addr().unwrap().to_string()).with_verbosity(0);
+
+        server.run(ok);
+    }
+
+    #[test]
+    fn slow_and_oversized_heads_are_refused() {
+        let server = Server::new(String::new())
+            .with_read_timeouts(Duration::from_millis(200), Duration::from_millis(200))
+            .with_header_limits(1024, 2);
+        let (addr, shutdown, running) = start(server, ok);
+
+        // Trickling bytes in doesn't extend the deadline.
+        let mut slow = TcpStream::connect(&addr).unwrap();
+        for _ in 0..4 {
+            slow.write_all(b"G").unwrap();
+            thread::sleep(Duration::from_millis(80));
+        }
+        let mut many = TcpStream::connect(&addr).unwrap();
+        many.write_all(b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n").unwrap();
+
+        assert!(read_all(slow).starts_with("HTTP/1.1 408 "));
+        assert!(read_all(many).starts_with("HTTP/1.1 431 "));
+        shutdown.shutdown();
+        running.join().unwrap();
+    }
+
+    #[test]
+    fn header_size_limit_holds_when_the_head_arrives_at_once() {
+        let (addr, shutdown, running) = start(Server::new(String::new()).with_header_limits(64, 100), ok);
+        let head = |len: usize| {
+            let padding = "x".repeat(len - "GET / HTTP/1.1\r\nX: \r\nConnection: close\r\n\r\n".len());
+            format!("GET / HTTP/1.1\r\nX: {padding}\r\nConnection: close\r\n\r\n")
+        };
+
+        let mut at_limit = TcpStream::connect(&addr).unwrap();
+        at_limit.write_all(head(64).as_bytes()).unwrap();
+        let mut over_limit = TcpStream::connect(&addr).unwrap();
+        over_limit.write_all(head(65).as_bytes()).unwrap();
+
+        assert!(read_all(at_limit).starts_with("HTTP/1.1 200 "));
+        assert!(read_all(over_limit).starts_with("HTTP/1.1 431 "));
+        shutdown.shutdown();
+        running.join().unwrap();
+    }
+
+    #[test]
+    fn limits_connections_per_ip() {
+        let server = Server::new(String::new()).with_max_connections_per_ip(1);
+        let (addr, shutdown, running) = start(server, ok);
+        // `start`'s probe connection may still hold the only slot briefly.
+        thread::sleep(Duration::from_millis(100));
+
+        let mut first = TcpStream::connect(&addr).unwrap();
+        first.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut reply = [0; 12];
+        first.read_exact(&mut reply).unwrap();
+        let second = TcpStream::connect(&addr).unwrap();
+
+        assert_eq!(&reply, b"HTTP/1.1 200");
+        assert!(read_all(second).starts_with("HTTP/1.1 429 "));
+        drop(first);
+        shutdown.shutdown();
+        running.join().unwrap();
     }
 }
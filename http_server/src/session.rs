@@ -0,0 +1,243 @@
+use super::http::{Cookie, Request, Response, SameSite};
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How long an unused session is kept by default.
+const DEFAULT_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// Keeps sessions in memory, keyed by an id the client holds in a cookie.
+///
+/// The cookie carries the id and an HMAC-SHA256 of it under `secret`, so a
+/// client can't make up ids; the data itself never leaves the server.
+/// Sessions expire after going unused for the TTL and don't survive a
+/// restart.
+///
+/// ```ignore
+/// let mut session = store.load(request);
+/// session.insert("user", "alice");
+/// session.regenerate(); // new id after logging in
+/// let mut response = Response::new(StatusCode::OK, None);
+/// store.save(session, &mut response);
+/// ```
+pub struct SessionStore {
+    secret: Vec<u8>,
+    cookie_name: String,
+    ttl: Duration,
+    secure: bool,
+    sessions: Mutex<HashMap<String, Stored>>,
+}
+
+struct Stored {
+    data: HashMap<String, String>,
+    expires: Instant,
+}
+
+/// One client's session data, as loaded for a request.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    id: Option<String>,
+    data: HashMap<String, String>,
+    regenerate: bool,
+    destroyed: bool,
+}
+
+impl Session {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.data.get(key).map(String::as_str)
+    }
+
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.data.insert(key.into(), value.into());
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.data.remove(key)
+    }
+
+    /// Whether the client has no session stored yet.
+    pub fn is_new(&self) -> bool {
+        self.id.is_none()
+    }
+
+    /// Moves the data to a fresh id when saved. Do this whenever privileges
+    /// change, such as on login, so an id planted beforehand is useless.
+    pub fn regenerate(&mut self) {
+        self.regenerate = true;
+    }
+
+    /// Forgets the session and tells the client to drop its cookie when
+    /// saved.
+    pub fn destroy(&mut self) {
+        self.data.clear();
+        self.destroyed = true;
+    }
+}
+
+impl SessionStore {
+    /// `secret` signs the session cookies; use at least 32 random bytes.
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+            cookie_name: "session".to_string(),
+            ttl: DEFAULT_TTL,
+            secure: false,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_cookie_name(mut self, cookie_name: impl Into<String>) -> Self {
+        self.cookie_name = cookie_name.into();
+        self
+    }
+
+    /// Sessions unused for `ttl` are dropped.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Marks the cookie `Secure`; turn this on when serving over HTTPS.
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// The session the request's cookie points to, or an empty new one if
+    /// there is no cookie, its signature is wrong, or the session expired.
+    pub fn load(&self, request: &Request) -> Session {
+        let Some(id) = request.cookie(&self.cookie_name).and_then(|value| self.verify(value)) else {
+            return Session::default();
+        };
+        let sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        match sessions.get(id) {
+            Some(stored) if stored.expires > Instant::now() => Session {
+                id: Some(id.to_string()),
+                data: stored.data.clone(),
+                ..Session::default()
+            },
+            _ => Session::default(),
+        }
+    }
+
+    /// Stores `session` and sets the cookie on `response` if the client
+    /// needs a new one. Empty new sessions aren't stored at all.
+    pub fn save(&self, session: Session, response: &mut Response) {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+
+        if session.destroyed {
+            if let Some(id) = &session.id {
+                sessions.remove(id);
+                response.set_cookie(&Cookie::removal(self.cookie_name.clone()).with_path("/"));
+            }
+            return;
+        }
+
+        let mut id = session.id;
+        if session.regenerate && let Some(old) = id.take() {
+            sessions.remove(&old);
+        }
+        if id.is_none() && session.data.is_empty() {
+            return;
+        }
+
+        let id = match id {
+            Some(id) => id,
+            None => {
+                sessions.retain(|_, stored| stored.expires > now);
+                let id = new_session_id();
+                response.set_cookie(&self.cookie(&id));
+                id
+            }
+        };
+        sessions.insert(
+            id,
+            Stored {
+                data: session.data,
+                expires: now + self.ttl,
+            },
+        );
+    }
+
+    fn cookie(&self, id: &str) -> Cookie {
+        Cookie::new(self.cookie_name.clone(), format!("{id}.{}", hex(&self.sign(id))))
+            .with_path("/")
+            .with_http_only(true)
+            .with_secure(self.secure)
+            .with_same_site(SameSite::Lax)
+    }
+
+    fn sign(&self, id: &str) -> Vec<u8> {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).expect("HMAC takes keys of any length");
+        mac.update(id.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    /// The id from a cookie value, if its signature checks out.
+    fn verify<'a>(&self, value: &'a str) -> Option<&'a str> {
+        let (id, signature) = value.split_once('.')?;
+        let signature = unhex(signature)?;
+        let mut mac = HmacSha256::new_from_slice(&self.secret).ok()?;
+        mac.update(id.as_bytes());
+        // Compares in constant time.
+        mac.verify_slice(&signature).ok()?;
+        Some(id)
+    }
+}
+
+fn new_session_id() -> String {
+    let mut bytes = [0; 16];
+    getrandom::fill(&mut bytes).expect("the OS has no source of randomness");
+    hex(&bytes)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::StatusCode;
+
+    fn request_with_cookie(cookie: &str) -> Vec<u8> {
+        format!("GET / HTTP/1.1\r\nCookie: {cookie}\r\n\r\n").into_bytes()
+    }
+
+    #[test]
+    fn round_trips_signed_sessions() {
+        let store = SessionStore::new(b"0123456789abcdef0123456789abcdef".to_vec());
+        let raw = request_with_cookie("other=1");
+        let mut session = store.load(&Request::try_from(&raw[..]).unwrap());
+        session.insert("user", "alice");
+        let mut response = Response::new(StatusCode::OK, None);
+        store.save(session, &mut response);
+
+        let set_cookie = response.headers().get("Set-Cookie").unwrap();
+        let cookie = set_cookie.split(';').next().unwrap();
+        assert!(set_cookie.contains("HttpOnly"));
+
+        let raw = request_with_cookie(cookie);
+        let session = store.load(&Request::try_from(&raw[..]).unwrap());
+        assert_eq!(session.get("user"), Some("alice"));
+
+        let tampered = cookie.replacen("session=", "session=0", 1);
+        let raw = request_with_cookie(&tampered);
+        assert!(store.load(&Request::try_from(&raw[..]).unwrap()).is_new());
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Stops a running `Server` from another thread, a test, or a signal.
+///
+/// Once `shutdown` is called the server stops accepting, finishes the
+/// requests already in flight, closes idle keep-alive connections, and
+/// returns from `run` once they are done or the shutdown timeout passes.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    stopping: AtomicBool,
+    /// Where each listener can be reached to wake it from `accept`.
+    listeners: Mutex<Vec<SocketAddr>>,
+    connections: Mutex<HashMap<u64, Connection>>,
+    drained: Condvar,
+    next_id: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Connection {
+    stream: TcpStream,
+    idle: bool,
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        if self.inner.stopping.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        for (_, connection) in self.connections().iter().filter(|(_, connection)| connection.idle) {
+            let _ = connection.stream.shutdown(Shutdown::Read);
+        }
+        let listeners = self.inner.listeners.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        for addr in listeners {
+            // Nothing is listening any more if this fails, which is fine.
+            let _ = TcpStream::connect_timeout(&addr, Duration::from_secs(1));
+        }
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.inner.stopping.load(Ordering::SeqCst)
+    }
+
+    /// Shuts down on SIGINT or SIGTERM (Ctrl-C on Windows). Only one
+    /// handle per process can do this.
+    pub fn shutdown_on_signals(&self) -> Result<(), ctrlc::Error> {
+        let handle = self.clone();
+        ctrlc::set_handler(move || {
+            println!("Shutting down");
+            handle.shutdown();
+        })
+    }
+
+    /// Records a listener bound to `addr` so `shutdown` can wake it.
+    pub(crate) fn add_listener(&self, addr: SocketAddr) {
+        let addr = match addr.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => SocketAddr::new(Ipv4Addr::LOCALHOST.into(), addr.port()),
+            IpAddr::V6(ip) if ip.is_unspecified() => SocketAddr::new(Ipv6Addr::LOCALHOST.into(), addr.port()),
+            _ => addr,
+        };
+        self.inner.listeners.lock().unwrap_or_else(|e| e.into_inner()).push(addr);
+    }
+
+    /// Counts `stream` as in flight until the returned guard is dropped.
+    /// Returns `None` if it couldn't be tracked, which only means it can't
+    /// be cut short at the deadline.
+    pub(crate) fn track(&self, stream: &TcpStream) -> Option<ConnectionGuard> {
+        let stream = stream.try_clone().ok()?;
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        self.connections().insert(id, Connection { stream, idle: false });
+        Some(ConnectionGuard {
+            handle: self.clone(),
+            id,
+        })
+    }
+
+    /// Waits up to `timeout` for every tracked connection to finish, then
+    /// closes whatever is left. Returns whether they all finished in time.
+    pub(crate) fn wait_for_connections(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut connections = self.connections();
+        while !connections.is_empty() {
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                for connection in connections.values() {
+                    let _ = connection.stream.shutdown(Shutdown::Both);
+                }
+                return false;
+            };
+            connections = self
+                .inner
+                .drained
+                .wait_timeout(connections, remaining)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        true
+    }
+
+    fn connections(&self) -> MutexGuard<'_, HashMap<u64, Connection>> {
+        self.inner.connections.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Keeps a connection counted as in flight for as long as it lives.
+pub(crate) struct ConnectionGuard {
+    handle: ShutdownHandle,
+    id: u64,
+}
+
+impl ConnectionGuard {
+    /// Marks the connection as waiting for its next request, so shutting
+    /// down can close it straight away instead of waiting.
+    pub(crate) fn set_idle(&self, idle: bool) {
+        let mut connections = self.handle.connections();
+        if let Some(connection) = connections.get_mut(&self.id) {
+            connection.idle = idle;
+            if idle && self.handle.is_shutting_down() {
+                let _ = connection.stream.shutdown(Shutdown::Read);
+            }
+        }
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self.handle.connections();
+        connections.remove(&self.id);
+        if connections.is_empty() {
+            self.handle.inner.drained.notify_all();
+        }
+    }
+}
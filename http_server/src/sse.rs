@@ -0,0 +1,357 @@
+use super::http::{BodyStream, Request, Response, StatusCode, Transport};
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::{ErrorKind, Result as IoResult};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a stream may go quiet by default before a keepalive comment is
+/// sent, which stops proxies timing it out and notices clients that left.
+const DEFAULT_KEEPALIVE: Duration = Duration::from_secs(15);
+/// How often an idle stream checks whether it should end.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// One server-sent event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: String,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    /// `data` may span several lines.
+    pub fn new(data: impl Into<String>) -> Self {
+        Self {
+            id: None,
+            event: None,
+            data: data.into(),
+            retry: None,
+        }
+    }
+
+    /// The client sends the last id it saw back as `Last-Event-ID` when it
+    /// reconnects. Line breaks and NULs are dropped.
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into().replace(['\r', '\n', '\0'], ""));
+        self
+    }
+
+    /// The event type, which picks the `addEventListener` listener in the
+    /// browser. Line breaks are dropped.
+    pub fn with_event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(event.into().replace(['\r', '\n'], ""));
+        self
+    }
+
+    /// How long the client should wait before reconnecting.
+    pub fn with_retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub fn data(&self) -> &str {
+        &self.data
+    }
+}
+
+/// The event in `text/event-stream` form, blank line included.
+impl Display for Event {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        if let Some(id) = &self.id {
+            writeln!(f, "id: {id}")?;
+        }
+        if let Some(event) = &self.event {
+            writeln!(f, "event: {event}")?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry.as_millis())?;
+        }
+        for line in self.data.split("\r\n").flat_map(|line| line.split(['\r', '\n'])) {
+            writeln!(f, "data: {line}")?;
+        }
+        writeln!(f)
+    }
+}
+
+/// The id a reconnecting client last saw, if it sent one.
+pub fn last_event_id<'a>(request: &'a Request) -> Option<&'a str> {
+    request.header("Last-Event-ID").map(str::trim).filter(|id| !id.is_empty())
+}
+
+/// Pushes events to one open stream.
+#[derive(Debug, Clone)]
+pub struct EventSender(Sender<Event>);
+
+impl EventSender {
+    /// Returns the event if the client has gone away.
+    pub fn send(&self, event: Event) -> Result<(), Event> {
+        self.0.send(event).map_err(|e| e.0)
+    }
+}
+
+/// A `text/event-stream` response fed by an `EventSender`. It stays open
+/// until every sender is dropped, the client disconnects or the server
+/// shuts down, holding a worker thread all the while.
+///
+/// ```ignore
+/// let (sender, stream) = EventStream::channel();
+/// thread::spawn(move || while sender.send(Event::new(status())).is_ok() {
+///     thread::sleep(Duration::from_secs(5));
+/// });
+/// stream.into_response()
+/// ```
+#[derive(Debug)]
+pub struct EventStream {
+    receiver: Receiver<Event>,
+    backlog: Vec<Event>,
+    keepalive: Duration,
+    retry: Option<Duration>,
+}
+
+impl EventStream {
+    pub fn channel() -> (EventSender, Self) {
+        let (sender, receiver) = mpsc::channel();
+        let stream = Self {
+            receiver,
+            backlog: Vec::new(),
+            keepalive: DEFAULT_KEEPALIVE,
+            retry: None,
+        };
+        (EventSender(sender), stream)
+    }
+
+    /// Events sent before any from the channel, e.g. the ones a
+    /// reconnecting client missed.
+    pub fn with_backlog(mut self, backlog: Vec<Event>) -> Self {
+        self.backlog = backlog;
+        self
+    }
+
+    pub fn with_keepalive(mut self, keepalive: Duration) -> Self {
+        self.keepalive = keepalive;
+        self
+    }
+
+    /// Tells the client how long to wait before reconnecting, up front.
+    pub fn with_retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    pub fn into_response(self) -> Response {
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "text/event-stream")
+            .header("Cache-Control", "no-cache")
+            // Keeps nginx from holding events back in its buffer.
+            .header("X-Accel-Buffering", "no")
+            .body(BodyStream::new(move |stream| self.run(stream)))
+    }
+
+    fn run(self, stream: &mut dyn Transport) -> IoResult<()> {
+        if let Some(retry) = self.retry {
+            write!(stream, "retry: {}\n\n", retry.as_millis())?;
+        }
+        for event in &self.backlog {
+            write!(stream, "{event}")?;
+        }
+        stream.flush()?;
+
+        let mut last_write = Instant::now();
+        loop {
+            match self.receiver.recv_timeout(POLL_INTERVAL.min(self.keepalive)) {
+                Ok(event) => {
+                    write!(stream, "{event}")?;
+                    stream.flush()?;
+                    last_write = Instant::now();
+                }
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                Err(RecvTimeoutError::Timeout) => {
+                    if is_closed(stream) {
+                        return Ok(());
+                    }
+                    if last_write.elapsed() >= self.keepalive {
+                        stream.write_all(b": keepalive\n\n")?;
+                        stream.flush()?;
+                        last_write = Instant::now();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether the client hung up or the server is shutting down, either of
+/// which leaves the socket readable at end of file.
+fn is_closed(stream: &dyn Transport) -> bool {
+    let socket = stream.socket();
+    if socket.set_read_timeout(Some(Duration::from_millis(1))).is_err() {
+        return true;
+    }
+    match socket.peek(&mut [0]) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(e) => !matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut),
+    }
+}
+
+/// Sends every event to all open streams, remembering the last few so a
+/// client that reconnects with `Last-Event-ID` gets what it missed.
+pub struct Broadcaster {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    subscribers: Vec<EventSender>,
+    history: VecDeque<Event>,
+    capacity: usize,
+    next_id: u64,
+}
+
+impl Broadcaster {
+    /// Keeps the last `history` events for replaying.
+    pub fn new(history: usize) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                subscribers: Vec::new(),
+                history: VecDeque::with_capacity(history),
+                capacity: history,
+                next_id: 1,
+            }),
+        }
+    }
+
+    /// Events without an id are numbered, since replaying needs one.
+    pub fn send(&self, event: Event) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let event = match event.id {
+            Some(_) => event,
+            None => {
+                let id = inner.next_id;
+                inner.next_id += 1;
+                event.with_id(id.to_string())
+            }
+        };
+        inner.subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        if inner.capacity > 0 {
+            if inner.history.len() == inner.capacity {
+                inner.history.pop_front();
+            }
+            inner.history.push_back(event);
+        }
+    }
+
+    /// A stream of every event from now on, preceded by those after the
+    /// request's `Last-Event-ID`. If that id is no longer remembered, all
+    /// remembered events are replayed.
+    pub fn subscribe(&self, request: &Request) -> EventStream {
+        let (sender, stream) = EventStream::channel();
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let backlog = match last_event_id(request) {
+            Some(last) => {
+                let seen = inner.history.iter().position(|event| event.id() == Some(last));
+                inner.history.iter().skip(seen.map_or(0, |i| i + 1)).cloned().collect()
+            }
+            None => Vec::new(),
+        };
+        inner.subscribers.push(sender);
+        stream.with_backlog(backlog)
+    }
+
+    /// How many streams are open, as of the last event sent.
+    pub fn subscribers(&self) -> usize {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).subscribers.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Server;
+    use crate::test_util::start;
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, TcpStream};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn formats_events() {
+        let event = Event::new("first\nsecond")
+            .with_id("7")
+            .with_event("update\n")
+            .with_retry(Duration::from_secs(3));
+
+        assert_eq!(event.to_string(), "id: 7\nevent: update\nretry: 3000\ndata: first\ndata: second\n\n");
+    }
+
+    #[test]
+    fn replays_events_after_last_event_id() {
+        let broadcaster = Broadcaster::new(2);
+        for data in ["a", "b", "c"] {
+            broadcaster.send(Event::new(data));
+        }
+        let raw = b"GET /events HTTP/1.1\r\nLast-Event-ID: 2\r\n\r\n";
+        let stream = broadcaster.subscribe(&Request::try_from(&raw[..]).unwrap());
+        broadcaster.send(Event::new("d"));
+
+        let backlog: Vec<_> = stream.backlog.iter().map(Event::data).collect();
+        assert_eq!(backlog, ["c"]);
+        assert_eq!(stream.receiver.try_recv().unwrap().id(), Some("4"));
+    }
+
+    #[test]
+    fn streams_through_the_server_until_the_sender_or_client_goes() {
+        let broadcaster = Arc::new(Broadcaster::new(0));
+        let subscribed = Arc::clone(&broadcaster);
+        let (addr, shutdown, running) = start(Server::new(String::new()), move |request: &Request| match request.path() {
+            "/broadcast" => subscribed.subscribe(request).with_keepalive(Duration::from_millis(50)).into_response(),
+            _ => {
+                let (sender, stream) = EventStream::channel();
+                thread::spawn(move || {
+                    sender.send(Event::new("one")).unwrap();
+                    thread::sleep(Duration::from_millis(150));
+                    sender.send(Event::new("two")).unwrap();
+                });
+                stream.with_keepalive(Duration::from_millis(50)).into_response()
+            }
+        });
+        let connect = |path: &str| {
+            let mut stream = TcpStream::connect(&addr).unwrap();
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+            stream
+        };
+
+        // The stream ends, and the connection closes, once the sender drops.
+        let mut response = String::new();
+        connect("/events").read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("Content-Type: text/event-stream\r\n"));
+        assert!(head.contains("Connection: close"));
+        assert!(!head.contains("Content-Length"));
+        assert!(body.starts_with("data: one\n\n"));
+        assert!(body.contains(": keepalive\n\n"));
+        assert!(body.ends_with("data: two\n\n"));
+
+        // A client hanging up ends its stream, and with it the subscription.
+        let client = connect("/broadcast");
+        let mut head = [0; 15];
+        (&client).read_exact(&mut head).unwrap();
+        assert_eq!(&head, b"HTTP/1.1 200 OK");
+        client.shutdown(Shutdown::Both).unwrap();
+        drop(client);
+        thread::sleep(Duration::from_millis(200));
+        broadcaster.send(Event::new("anyone?"));
+        assert_eq!(broadcaster.subscribers(), 0);
+
+        shutdown.shutdown();
+        running.join().unwrap();
+    }
+}
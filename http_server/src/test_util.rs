@@ -0,0 +1,34 @@
+//! Scaffolding shared by tests that need a running server or files on disk.
+
+use crate::server::{Handler, Server};
+use crate::shutdown::ShutdownHandle;
+use std::fs;
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Runs `server` quietly on a free local port until the returned handle is
+/// used, and returns the address it is listening on once it accepts
+/// connections.
+pub fn start(server: Server, handler: impl Handler + 'static) -> (String, ShutdownHandle, JoinHandle<()>) {
+    let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+    let mut server = server.with_addr(addr.clone()).with_verbosity(0);
+    let shutdown = server.shutdown_handle();
+    let running = thread::spawn(move || server.run(handler));
+    while TcpStream::connect(&addr).is_err() {
+        thread::sleep(Duration::from_millis(10));
+    }
+    (addr, shutdown, running)
+}
+
+/// A fresh, empty directory under the system temp dir, named after `name`
+/// and this test run. Its canonical path is returned, since that is what
+/// handlers compare against.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("http_server-{}-{name}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::canonicalize(dir).unwrap()
+}
@@ -0,0 +1,167 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed set of worker threads fed from a bounded queue.
+///
+/// `execute` blocks once the queue is full, which pushes back on whoever
+/// is producing work. Dropping the pool lets the workers finish every job
+/// already queued before it returns.
+pub struct ThreadPool {
+    workers: Vec<Worker>,
+    sender: Option<SyncSender<Job>>,
+}
+
+struct Worker {
+    id: usize,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    /// Panics if `size` is zero.
+    pub fn new(size: usize, queue_size: usize) -> Self {
+        assert!(size > 0, "a thread pool needs at least one worker");
+
+        let (sender, receiver) = mpsc::sync_channel(queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..size)
+            .map(|id| Worker::new(id, Arc::clone(&receiver)))
+            .collect();
+
+        Self {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if let Some(sender) = &self.sender {
+            // Sending only fails once every worker has gone, which can't
+            // happen while the pool still holds the sender.
+            sender.send(Box::new(f)).ok();
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // Closing the channel lets workers drain the queue, then stop.
+        drop(self.sender.take());
+
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take()
+                && thread.join().is_err()
+            {
+                println!("Worker {} stopped abnormally", worker.id);
+            }
+        }
+    }
+}
+
+impl Worker {
+    fn new(id: usize, receiver: Arc<Mutex<Receiver<Job>>>) -> Self {
+        let thread = thread::spawn(move || loop {
+            // The guard is dropped before the job runs so other workers can
+            // pick up work meanwhile.
+            let job = match receiver.lock() {
+                Ok(receiver) => receiver.recv(),
+                Err(_) => return,
+            };
+            match job {
+                Ok(job) => {
+                    // A panicking job must not take the worker down with it.
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        println!("Worker {id} recovered from a panicking job");
+                    }
+                }
+                Err(_) => return,
+            }
+        });
+
+        Self {
+            id,
+            thread: Some(thread),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Barrier;
+    use std::time::Duration;
+
+    #[test]
+    fn runs_jobs_concurrently_up_to_the_worker_count() {
+        let pool = ThreadPool::new(3, 10);
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        // Only passes once three jobs are running at the same time.
+        let barrier = Arc::new(Barrier::new(3));
+        for _ in 0..6 {
+            let (running, most, barrier) = (Arc::clone(&running), Arc::clone(&most), Arc::clone(&barrier));
+            pool.execute(move || {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                most.fetch_max(now, Ordering::SeqCst);
+                barrier.wait();
+                thread::sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        drop(pool);
+
+        assert_eq!(most.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn execute_blocks_while_the_queue_is_full() {
+        let pool = Arc::new(ThreadPool::new(1, 1));
+        let (release, gate) = mpsc::channel::<()>();
+        let gate = Mutex::new(gate);
+        // Occupies the only worker, then fills the only queue slot.
+        pool.execute(move || {
+            gate.lock().unwrap().recv().ok();
+        });
+        pool.execute(|| {});
+
+        let queued = Arc::new(AtomicBool::new(false));
+        let producer = {
+            let (pool, queued) = (Arc::clone(&pool), Arc::clone(&queued));
+            thread::spawn(move || {
+                pool.execute(|| {});
+                queued.store(true, Ordering::SeqCst);
+            })
+        };
+        thread::sleep(Duration::from_millis(100));
+        assert!(!queued.load(Ordering::SeqCst));
+
+        release.send(()).unwrap();
+        producer.join().unwrap();
+        assert!(queued.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn dropping_the_pool_finishes_queued_jobs_and_joins_workers() {
+        let pool = ThreadPool::new(2, 10);
+        let done = Arc::new(AtomicUsize::new(0));
+        for _ in 0..6 {
+            let done = Arc::clone(&done);
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(20));
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        pool.execute(|| panic!("a failing job"));
+        drop(pool);
+
+        assert_eq!(done.load(Ordering::SeqCst), 6);
+    }
+}
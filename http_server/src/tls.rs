@@ -0,0 +1,241 @@
+use rustls::crypto::ring::{self as provider, sign};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{CertifiedKey, SigningKey};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use crate::http::{Method, Request, Response, StatusCode, Transport};
+use crate::server::Handler;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result as IoResult};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Certificates for an HTTPS listener: a default one, plus any number
+/// chosen by the server name the client asks for (SNI).
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    default: Arc<CertifiedKey>,
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl TlsConfig {
+    /// Loads a PEM certificate chain and its PEM private key.
+    pub fn from_pem_files(cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> IoResult<Self> {
+        Ok(Self {
+            default: load_pem_files(cert_path.as_ref(), key_path.as_ref())?,
+            by_name: HashMap::new(),
+        })
+    }
+
+    pub fn from_pem(cert_pem: &[u8], key_pem: &[u8]) -> IoResult<Self> {
+        Ok(Self {
+            default: load_pem(cert_pem, key_pem)?,
+            by_name: HashMap::new(),
+        })
+    }
+
+    /// Serves this certificate to clients asking for `server_name`, and the
+    /// default one to everyone else.
+    pub fn with_sni_pem_files(
+        mut self,
+        server_name: &str,
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> IoResult<Self> {
+        let key = load_pem_files(cert_path.as_ref(), key_path.as_ref())?;
+        self.by_name.insert(server_name.to_ascii_lowercase(), key);
+        Ok(self)
+    }
+
+    pub fn with_sni_pem(mut self, server_name: &str, cert_pem: &[u8], key_pem: &[u8]) -> IoResult<Self> {
+        self.by_name.insert(server_name.to_ascii_lowercase(), load_pem(cert_pem, key_pem)?);
+        Ok(self)
+    }
+
+    pub fn server_config(&self) -> IoResult<Arc<ServerConfig>> {
+        let mut config = ServerConfig::builder_with_provider(Arc::new(provider::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(Error::other)?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(self.clone()));
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(Arc::new(config))
+    }
+}
+
+impl ResolvesServerCert for TlsConfig {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let key = client_hello
+            .server_name()
+            .and_then(|name| self.by_name.get(&name.to_ascii_lowercase()))
+            .unwrap_or(&self.default);
+        Some(Arc::clone(key))
+    }
+}
+
+impl Transport for StreamOwned<ServerConnection, TcpStream> {
+    fn socket(&self) -> &TcpStream {
+        &self.sock
+    }
+}
+
+/// Sends every plain HTTP request to the same host and target over HTTPS.
+pub struct HttpsRedirect {
+    https_port: Option<u16>,
+}
+
+impl HttpsRedirect {
+    /// `https_port` is left out of the `Location` when it is 443 or unknown.
+    pub fn new(https_port: Option<u16>) -> Self {
+        Self { https_port }
+    }
+}
+
+impl Handler for HttpsRedirect {
+    fn handle_request(&self, request: &Request) -> Response {
+        let Some(host) = request.header("Host").map(host_name).filter(|host| is_valid_host(host)) else {
+            return Response::new(StatusCode::BadRequest, None);
+        };
+        let port = match self.https_port {
+            Some(port) if port != 443 => format!(":{port}"),
+            _ => String::new(),
+        };
+        let target = match request.target() {
+            target if target.starts_with('/') => target,
+            _ => "/",
+        };
+
+        // 308 keeps the method and body of anything but a plain fetch.
+        let status = match request.method() {
+            Method::GET | Method::HEAD => StatusCode::MovedPermanently,
+            _ => StatusCode::PermanentRedirect,
+        };
+        Response::builder()
+            .status(status)
+            .header("Location", format!("https://{host}{port}{target}"))
+            .build()
+    }
+}
+
+/// The `Host` header without its port, e.g. `[::1]` from `[::1]:8080`.
+fn host_name(host: &str) -> &str {
+    match host.rfind(']') {
+        Some(i) => &host[..=i],
+        None => host.split(':').next().unwrap_or(host),
+    }
+}
+
+fn is_valid_host(host: &str) -> bool {
+    !host.is_empty()
+        && host
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'-' | b'[' | b']' | b':'))
+}
+
+fn load_pem_files(cert_path: &Path, key_path: &Path) -> IoResult<Arc<CertifiedKey>> {
+    let cert_pem = std::fs::read(cert_path)?;
+    let key_pem = std::fs::read(key_path)?;
+    load_pem(&cert_pem, &key_pem).map_err(|e| {
+        Error::new(e.kind(), format!("{} / {}: {e}", cert_path.display(), key_path.display()))
+    })
+}
+
+fn load_pem(cert_pem: &[u8], key_pem: &[u8]) -> IoResult<Arc<CertifiedKey>> {
+    let key = CertifiedKey::new(load_certs(cert_pem)?, load_key(key_pem)?);
+    key.keys_match().map_err(|e| {
+        Error::new(ErrorKind::InvalidData, format!("private key does not match the certificate: {e}"))
+    })?;
+    Ok(Arc::new(key))
+}
+
+/// The certificate chain in `cert_pem`, which must hold at least one.
+pub(crate) fn load_certs(cert_pem: &[u8]) -> IoResult<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_slice_iter(cert_pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("bad certificate: {e}")))?;
+    if certs.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, "no certificate found"));
+    }
+    Ok(certs)
+}
+
+/// The private key in `key_pem`, ready for signing.
+pub(crate) fn load_key(key_pem: &[u8]) -> IoResult<Arc<dyn SigningKey>> {
+    let invalid = |e| Error::new(ErrorKind::InvalidData, e);
+    let key = PrivateKeyDer::from_pem_slice(key_pem).map_err(|e| invalid(format!("bad private key: {e}")))?;
+    sign::any_supported_type(&key).map_err(|e| invalid(format!("unusable private key: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, ClientConnection, RootCertStore};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Handshakes with `config` as `server_name` and returns the
+    /// certificate the server presented.
+    fn presented_cert(config: Arc<ServerConfig>, roots: RootCertStore, server_name: &str) -> CertificateDer<'static> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut tls = StreamOwned::new(ServerConnection::new(config).unwrap(), stream);
+            while tls.conn.is_handshaking() {
+                tls.conn.complete_io(&mut tls.sock).unwrap();
+            }
+        });
+
+        let client_config = ClientConfig::builder_with_provider(Arc::new(provider::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let name = ServerName::try_from(server_name.to_string()).unwrap();
+        let mut client = ClientConnection::new(Arc::new(client_config), name).unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        while client.is_handshaking() {
+            client.complete_io(&mut stream).unwrap();
+        }
+        server.join().unwrap();
+
+        client.peer_certificates().unwrap()[0].clone()
+    }
+
+    #[test]
+    fn picks_certificate_by_server_name() {
+        let default = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let other = rcgen::generate_simple_self_signed(vec!["other.test".to_string()]).unwrap();
+
+        let config = TlsConfig::from_pem(default.cert.pem().as_bytes(), default.signing_key.serialize_pem().as_bytes())
+            .unwrap()
+            .with_sni_pem("other.test", other.cert.pem().as_bytes(), other.signing_key.serialize_pem().as_bytes())
+            .unwrap()
+            .server_config()
+            .unwrap();
+        let mut roots = RootCertStore::empty();
+        roots.add(default.cert.der().clone()).unwrap();
+        roots.add(other.cert.der().clone()).unwrap();
+
+        assert_eq!(&presented_cert(config.clone(), roots.clone(), "other.test"), other.cert.der());
+        assert_eq!(&presented_cert(config, roots, "localhost"), default.cert.der());
+    }
+
+    #[test]
+    fn redirects_to_https_keeping_host_and_target() {
+        let redirect = HttpsRedirect::new(Some(8443));
+        let raw = b"POST /a%20b?x=1 HTTP/1.1\r\nHost: example.com:8080\r\n\r\n";
+        let response = redirect.handle_request(&Request::try_from(&raw[..]).unwrap());
+
+        assert_eq!(response.status_code(), StatusCode::PermanentRedirect);
+        assert_eq!(response.headers().get("Location"), Some("https://example.com:8443/a%20b?x=1"));
+    }
+
+    #[test]
+    fn rejects_files_without_a_certificate() {
+        assert!(TlsConfig::from_pem(b"", b"").is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use super::http::{Request, Response, StatusCode};
+use super::server::Handler;
+
+/// Picks a handler by the request's `Host`, so one server can serve several
+/// sites. Names match case-insensitively and without the port; requests
+/// for a host nobody claims go to the first one added.
+///
+/// ```ignore
+/// let hosts = VirtualHosts::new()
+///     .host(vec!["example.com".into(), "www.example.com".into()], WebsiteHandler::new(site))
+///     .host(vec!["docs.example.com".into()], WebsiteHandler::new(docs));
+/// ```
+#[derive(Default)]
+pub struct VirtualHosts {
+    hosts: Vec<(Vec<String>, Box<dyn Handler>)>,
+}
+
+impl VirtualHosts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn host(mut self, names: Vec<String>, handler: impl Handler + 'static) -> Self {
+        let names = names.iter().map(|name| normalize(name)).collect();
+        self.hosts.push((names, Box::new(handler)));
+        self
+    }
+
+    fn handler_for(&self, host: Option<&str>) -> Option<&dyn Handler> {
+        let host = host.map(|host| normalize(strip_port(host)));
+        self.hosts
+            .iter()
+            .find(|(names, _)| host.as_ref().is_some_and(|host| names.contains(host)))
+            .or(self.hosts.first())
+            .map(|(_, handler)| handler.as_ref())
+    }
+}
+
+impl Handler for VirtualHosts {
+    fn handle_request(&self, request: &Request) -> Response {
+        match self.handler_for(request.header("Host")) {
+            Some(handler) => handler.handle_request(request),
+            None => Response::error(StatusCode::NotFound),
+        }
+    }
+}
+
+/// `example.com:8080` and `[::1]:8080` without the port.
+fn strip_port(host: &str) -> &str {
+    let host = host.trim();
+    if let Some(rest) = host.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest);
+    }
+    match host.rsplit_once(':') {
+        Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host,
+    }
+}
+
+/// The form host names are compared in: lowercase, without the trailing
+/// dot of a fully qualified name.
+pub(crate) fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_host_by_name_and_falls_back_to_the_first() {
+        let hosts = VirtualHosts::new()
+            .host(vec!["example.com".into()], |_: &Request| Response::new(StatusCode::OK, Some(b"main".to_vec())))
+            .host(vec!["docs.example.com".into()], |_: &Request| Response::new(StatusCode::OK, Some(b"docs".to_vec())));
+        let body = |host: &str| {
+            let raw = format!("GET / HTTP/1.1\r\nHost: {host}\r\n\r\n");
+            let response = hosts.handle_request(&Request::try_from(raw.as_bytes()).unwrap());
+            String::from_utf8(response.body().and_then(|body| body.as_bytes()).unwrap().to_vec()).unwrap()
+        };
+
+        assert_eq!(body("Docs.Example.com:8080"), "docs");
+        assert_eq!(body("example.com."), "main");
+        assert_eq!(body("unknown.test"), "main");
+    }
+}
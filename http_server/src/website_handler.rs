@@ -1,21 +1,62 @@
-use super::http::{Request, Response, StatusCode, Method};
+use super::http::range::{self, RangeRequest};
+use super::directory_listing;
+use super::http::compression::{self, Encoding};
+use super::http::{date, mime, percent_encoding, Request, Response, ResponseBody, StatusCode, Method};
 use super::server::Handler;
-use std::fs;
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File, Metadata};
+use std::hash::BuildHasher;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const ALLOWED_METHODS: &str = "GET, HEAD, OPTIONS";
+/// Precompressed copies looked for next to a file, e.g. `app.js.br`.
+const PRECOMPRESSED: [(Encoding, &str); 2] = [(Encoding::Brotli, "br"), (Encoding::Gzip, "gz")];
 
 pub struct WebsiteHandler {
     public_path: String,
+    index_files: Vec<String>,
+    directory_listing: bool,
+    hidden_files: bool,
 }
 
 impl WebsiteHandler {
     pub fn new(public_path: String) -> Self {
-        Self { public_path }
+        Self {
+            public_path,
+            index_files: vec!["index.html".to_string()],
+            directory_listing: false,
+            hidden_files: false,
+        }
+    }
+
+    /// File names tried, in order, when a directory is requested.
+    pub fn with_index_files(mut self, index_files: Vec<String>) -> Self {
+        self.index_files = index_files;
+        self
+    }
+
+    /// Whether directories without an index file get a generated listing
+    /// instead of a 404. Off by default.
+    pub fn with_directory_listing(mut self, enabled: bool) -> Self {
+        self.directory_listing = enabled;
+        self
+    }
+
+    /// Whether directory listings include dotfiles. Off by default.
+    pub fn with_hidden_files(mut self, enabled: bool) -> Self {
+        self.hidden_files = enabled;
+        self
     }
-    fn read_file(&self, file_path: &str) -> Option<String> {
+
+    /// Maps a request path onto a file under the public directory, refusing
+    /// anything that resolves outside it.
+    fn resolve(&self, file_path: &str) -> Option<PathBuf> {
         let path = format!("{}/{}", self.public_path,  file_path);
         match fs::canonicalize(path) {
             Ok(path) => {
                 if path.starts_with(&self.public_path) {
-                    fs::read_to_string(path).ok()
+                    Some(path)
                 } else {
                     println!("Directory Traversal Attack Attempted: {}", file_path);
                     None
@@ -24,23 +65,479 @@ impl WebsiteHandler {
             Err(_) => None
         }
     }
+
+    fn serve_path(&self, request: &Request) -> Response {
+        let request_path = request.path();
+        let file_path = request_path.strip_prefix('/').unwrap_or(request_path);
+        let Some(path) = self.resolve(file_path) else {
+            return Response::new(StatusCode::NotFound, None);
+        };
+        if !path.is_dir() {
+            return self.serve_file(request, &path);
+        }
+
+        // Relative links in a directory's page only work from `/dir/`.
+        if !request_path.ends_with('/') {
+            let query = request.target().split_once('?').map_or(String::new(), |(_, query)| format!("?{query}"));
+            return Response::builder()
+                .status(StatusCode::MovedPermanently)
+                .header("Location", format!("{}/{query}", percent_encoding::encode_path(request_path)))
+                .build();
+        }
+
+        for index_file in &self.index_files {
+            if let Some(index) = self.resolve(&format!("{file_path}{index_file}"))
+                && index.is_file()
+            {
+                return self.serve_file(request, &index);
+            }
+        }
+
+        if self.directory_listing {
+            directory_listing::render(request, &path, self.hidden_files)
+        } else {
+            Response::new(StatusCode::NotFound, None)
+        }
+    }
+
+    /// Finds the best precompressed sibling of `path` the client accepts.
+    /// Range requests always get the plain file, since ranges would refer to
+    /// the compressed bytes.
+    fn precompressed(&self, request: &Request, path: &Path) -> (Option<(PathBuf, Encoding)>, bool) {
+        let siblings: Vec<(PathBuf, Encoding)> = PRECOMPRESSED
+            .iter()
+            .filter_map(|(encoding, extension)| {
+                let mut sibling = path.as_os_str().to_owned();
+                sibling.push(format!(".{extension}"));
+                let sibling = fs::canonicalize(sibling).ok()?;
+                (sibling.starts_with(&self.public_path) && sibling.is_file()).then_some((sibling, *encoding))
+            })
+            .collect();
+        if siblings.is_empty() || request.header("Range").is_some() {
+            return (None, !siblings.is_empty());
+        }
+
+        let available: Vec<Encoding> = siblings.iter().map(|(_, encoding)| *encoding).collect();
+        let chosen = compression::negotiate(request.header("Accept-Encoding"), &available)
+            .and_then(|chosen| siblings.into_iter().find(|(_, encoding)| *encoding == chosen));
+        (chosen, true)
+    }
+
+    fn serve_file(&self, request: &Request, path: &Path) -> Response {
+        let content_type = mime::from_path(path);
+        let (precompressed, has_siblings) = self.precompressed(request, path);
+        let (path, encoding) = match &precompressed {
+            Some((sibling, encoding)) => (sibling.as_path(), Some(*encoding)),
+            None => (path, None),
+        };
+
+        let Ok(file) = File::open(path) else {
+            return Response::new(StatusCode::NotFound, None);
+        };
+        let metadata = match file.metadata() {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => return Response::new(StatusCode::NotFound, None),
+        };
+
+        let etag = etag(&metadata);
+        let last_modified = metadata.modified().ok().map(date::format);
+        let len = metadata.len();
+
+        let mut response = Response::builder()
+            .header("ETag", etag.clone())
+            .header("Accept-Ranges", "bytes");
+        if let Some(encoding) = encoding {
+            response = response.header("Content-Encoding", encoding.as_str());
+        }
+        if has_siblings {
+            response = response.header("Vary", "Accept-Encoding");
+        }
+        if let Some(last_modified) = &last_modified {
+            response = response.header("Last-Modified", last_modified.clone());
+        }
+
+        if is_not_modified(request, &etag, &metadata) {
+            return response.status(StatusCode::NotModified).build();
+        }
+
+        let ranges = match request.header("Range") {
+            Some(value) if if_range_matches(request, &etag, last_modified.as_deref()) => {
+                range::parse(value, len)
+            }
+            _ => RangeRequest::Full,
+        };
+
+        match ranges {
+            RangeRequest::Full => response
+                .status(StatusCode::OK)
+                .header("Content-Type", content_type)
+                .body(ResponseBody::file_range(file, 0, len)),
+            RangeRequest::Unsatisfiable => response
+                .status(StatusCode::RangeNotSatisfiable)
+                .header("Content-Range", format!("bytes */{len}"))
+                .build(),
+            RangeRequest::Partial(ranges) if ranges.len() == 1 => response
+                .status(StatusCode::PartialContent)
+                .header("Content-Type", content_type)
+                .header("Content-Range", ranges[0].content_range(len))
+                .body(ResponseBody::file_range(file, ranges[0].start, ranges[0].len())),
+            RangeRequest::Partial(ranges) => {
+                let boundary = format!("{:016x}", random_seed());
+                let mut parts = Vec::with_capacity(ranges.len() * 2 + 1);
+                for range in &ranges {
+                    let Ok(file) = file.try_clone() else {
+                        return Response::new(StatusCode::InternalServerError, None);
+                    };
+                    let part_head = format!(
+                        "\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: {}\r\n\r\n",
+                        range.content_range(len)
+                    );
+                    parts.push(part_head.into());
+                    parts.push(ResponseBody::file_range(file, range.start, range.len()));
+                }
+                parts.push(format!("\r\n--{boundary}--\r\n").into());
+
+                response
+                    .status(StatusCode::PartialContent)
+                    .header("Content-Type", format!("multipart/byteranges; boundary={boundary}"))
+                    .body(ResponseBody::Parts(parts))
+            }
+        }
+    }
+}
+
+/// A validator that changes whenever the file's size or modification time
+/// does.
+fn etag(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", metadata.len(), modified.as_nanos())
+}
+
+/// Evaluates `If-None-Match`, or `If-Modified-Since` when there is no
+/// `If-None-Match`, as RFC 9110 orders them.
+fn is_not_modified(request: &Request, etag: &str, metadata: &Metadata) -> bool {
+    if let Some(if_none_match) = request.header("If-None-Match") {
+        // Weak comparison: a W/ prefix on either side doesn't matter.
+        return if_none_match.split(',').map(str::trim).any(|tag| {
+            tag == "*" || tag.trim_start_matches("W/") == etag.trim_start_matches("W/")
+        });
+    }
+
+    let since = request.header("If-Modified-Since").and_then(date::parse);
+    match (since, metadata.modified()) {
+        // HTTP dates have whole-second resolution, so compare at that.
+        (Some(since), Ok(modified)) => {
+            let secs = |time: std::time::SystemTime| {
+                time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+            };
+            secs(modified) <= secs(since)
+        }
+        _ => false,
+    }
+}
+
+/// A `Range` only applies if `If-Range`, when sent, still names the current
+/// version of the file. Only strong validators count here.
+fn if_range_matches(request: &Request, etag: &str, last_modified: Option<&str>) -> bool {
+    match request.header("If-Range").map(str::trim) {
+        None => true,
+        Some(tag) if tag.starts_with('"') => tag == etag,
+        Some(date) => Some(date) == last_modified,
+    }
+}
+
+/// Good enough to keep multipart boundaries from colliding with file
+/// contents; not for anything secret.
+fn random_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    RandomState::new().hash_one(nanos)
 }
 
 impl Handler for WebsiteHandler {
-    fn handle_request(&mut self, request: &Request) -> Response {
+    fn handle_request(&self, request: &Request) -> Response {
         //Response::new(StatusCode::OK, Some("<h1>TEST</h1>".to_string()))
 
         match request.method() {
-            Method::GET =>match request.path() {
-                "/" => Response::new(StatusCode::OK, self.read_file("index.html")),
-                "/hello" => Response::new(StatusCode::OK, Some("<h1>Hello</h1>".to_string())),
-                
-                path => match self.read_file(&path[1..]) {
-                    Some(content) => Response::new(StatusCode::OK, Some(content)),
-                    None => Response::new(StatusCode::NotFound, None),
-                }
+            // HEAD is answered like GET; the server leaves the body off.
+            Method::GET | Method::HEAD =>match request.path() {
+                "/hello" => Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", "text/html; charset=utf-8")
+                    .body("<h1>Hello</h1>"),
+
+                _ => self.serve_path(request),
             }
-            _ => Response::new(StatusCode::NotFound, None)
+            Method::OPTIONS => Response::builder()
+                .status(StatusCode::NoContent)
+                .header("Allow", ALLOWED_METHODS)
+                .build(),
+            _ => Response::builder()
+                .status(StatusCode::MethodNotAllowed)
+                .header("Allow", ALLOWED_METHODS)
+                .build(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    /// A fresh public directory holding `site/index.html`, `files/a.txt`
+    /// and `files/.secret`.
+    fn public_dir(name: &str) -> PathBuf {
+        let dir = temp_dir(&format!("website_handler-{name}"));
+        fs::create_dir_all(dir.join("site")).unwrap();
+        fs::create_dir_all(dir.join("files/sub")).unwrap();
+        fs::write(dir.join("site/index.html"), "<h1>home</h1>").unwrap();
+        fs::write(dir.join("files/a.txt"), "hello").unwrap();
+        fs::write(dir.join("files/.secret"), "hidden").unwrap();
+        dir
+    }
+
+    fn get(handler: &WebsiteHandler, raw: &str) -> Response {
+        handler.handle_request(&Request::try_from(raw.as_bytes()).unwrap())
+    }
+
+    /// The response as written for a GET, or for a HEAD with `head_only`.
+    fn sent(response: &Response, head_only: bool) -> String {
+        let mut sent = Vec::new();
+        match head_only {
+            true => response.send_head(&mut sent).unwrap(),
+            false => response.send(&mut sent).unwrap(),
+        }
+        String::from_utf8_lossy(&sent).into_owned()
+    }
+
+    fn body(response: &Response) -> String {
+        String::from_utf8(response.body().and_then(|body| body.as_bytes()).unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn directories_redirect_then_serve_their_index() {
+        let dir = public_dir("index");
+        let handler = WebsiteHandler::new(dir.to_string_lossy().into_owned());
+
+        let response = get(&handler, "GET /site?lang=en HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status_code(), StatusCode::MovedPermanently);
+        assert_eq!(response.headers().get("Location"), Some("/site/?lang=en"));
+
+        let mut sent = Vec::new();
+        get(&handler, "GET /site/ HTTP/1.1\r\n\r\n").send(&mut sent).unwrap();
+        let sent = String::from_utf8(sent).unwrap();
+        assert!(sent.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(sent.contains("Content-Type: text/html"));
+        assert!(sent.ends_with("<h1>home</h1>"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn lists_directories_as_html_and_json_without_dotfiles() {
+        let dir = public_dir("listing");
+        let handler = WebsiteHandler::new(dir.to_string_lossy().into_owned()).with_directory_listing(true);
+
+        let html = get(&handler, "GET /files/ HTTP/1.1\r\n\r\n");
+        assert_eq!(html.headers().get("Content-Type"), Some("text/html; charset=utf-8"));
+        let html = body(&html);
+        assert!(html.contains("<a href=\"sub/\">sub/</a>"));
+        assert!(html.contains("<a href=\"a.txt\">a.txt</a></td><td>5</td>"));
+        assert!(!html.contains(".secret"));
+
+        let json = get(&handler, "GET /files/?format=json HTTP/1.1\r\n\r\n");
+        assert_eq!(json.headers().get("Content-Type"), Some("application/json"));
+        let json: serde_json::Value = serde_json::from_str(&body(&json)).unwrap();
+        let names: Vec<_> = json.as_array().unwrap().iter().map(|entry| entry["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["sub", "a.txt"]);
+        assert_eq!(json[0]["type"], "directory");
+        assert_eq!(json[1]["size"], 5);
+
+        let handler = handler.with_hidden_files(true);
+        assert!(body(&get(&handler, "GET /files/ HTTP/1.1\r\n\r\n")).contains(".secret"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn files_get_a_content_type_from_their_extension() {
+        let dir = public_dir("types");
+        for name in ["logo.PNG", "app.js", "data.json", "notes.unknown", "README"] {
+            fs::write(dir.join(name), [0x00, 0xff]).unwrap();
+        }
+        let handler = WebsiteHandler::new(dir.to_string_lossy().into_owned());
+        let content_type = |name: &str| {
+            let mut sent = Vec::new();
+            get(&handler, &format!("GET /{name} HTTP/1.1\r\n\r\n")).send(&mut sent).unwrap();
+            assert!(sent.ends_with(b"Content-Length: 2\r\n\r\n\x00\xff"));
+            let head = String::from_utf8_lossy(&sent).into_owned();
+            head.lines().find_map(|line| line.strip_prefix("Content-Type: ")).map(str::to_string)
+        };
+
+        assert_eq!(content_type("logo.PNG").as_deref(), Some("image/png"));
+        assert_eq!(content_type("app.js").as_deref(), Some("text/javascript; charset=utf-8"));
+        assert_eq!(content_type("data.json").as_deref(), Some("application/json"));
+        assert_eq!(content_type("notes.unknown").as_deref(), Some("application/octet-stream"));
+        assert_eq!(content_type("README").as_deref(), Some("application/octet-stream"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn head_options_and_other_methods() {
+        let dir = public_dir("methods");
+        let handler = WebsiteHandler::new(dir.to_string_lossy().into_owned());
+
+        let get_sent = sent(&get(&handler, "GET /files/a.txt HTTP/1.1\r\n\r\n"), false);
+        let head_sent = sent(&get(&handler, "HEAD /files/a.txt HTTP/1.1\r\n\r\n"), true);
+        assert_eq!(get_sent, format!("{head_sent}hello"));
+        assert!(head_sent.contains("Content-Length: 5\r\n"));
+
+        let options = get(&handler, "OPTIONS /files/a.txt HTTP/1.1\r\n\r\n");
+        assert_eq!(options.status_code(), StatusCode::NoContent);
+        assert_eq!(options.headers().get("Allow"), Some("GET, HEAD, OPTIONS"));
+
+        let post = get(&handler, "POST /files/a.txt HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        assert_eq!(post.status_code(), StatusCode::MethodNotAllowed);
+        assert_eq!(post.headers().get("Allow"), Some("GET, HEAD, OPTIONS"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn conditional_gets_answer_304() {
+        let dir = public_dir("conditional");
+        let handler = WebsiteHandler::new(dir.to_string_lossy().into_owned());
+        let status = |headers: &str| get(&handler, &format!("GET /files/a.txt HTTP/1.1\r\n{headers}\r\n")).status_code();
+
+        let full = get(&handler, "GET /files/a.txt HTTP/1.1\r\n\r\n");
+        assert_eq!(full.status_code(), StatusCode::OK);
+        let etag = full.headers().get("ETag").unwrap().to_string();
+        let last_modified = full.headers().get("Last-Modified").unwrap().to_string();
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+
+        assert_eq!(status(&format!("If-None-Match: {etag}\r\n")), StatusCode::NotModified);
+        assert_eq!(status(&format!("If-None-Match: \"other\", W/{etag}\r\n")), StatusCode::NotModified);
+        assert_eq!(status("If-None-Match: *\r\n"), StatusCode::NotModified);
+        assert_eq!(status("If-None-Match: \"other\"\r\n"), StatusCode::OK);
+
+        assert_eq!(status(&format!("If-Modified-Since: {last_modified}\r\n")), StatusCode::NotModified);
+        assert_eq!(status("If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n"), StatusCode::OK);
+        // If-None-Match is evaluated instead of If-Modified-Since, not as well.
+        let both = format!("If-None-Match: \"other\"\r\nIf-Modified-Since: {last_modified}\r\n");
+        assert_eq!(status(&both), StatusCode::OK);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn serves_single_and_multiple_ranges() {
+        let dir = public_dir("ranges");
+        let handler = WebsiteHandler::new(dir.to_string_lossy().into_owned());
+        let range = |headers: &str| get(&handler, &format!("GET /files/a.txt HTTP/1.1\r\n{headers}\r\n"));
+
+        let single = sent(&range("Range: bytes=1-3\r\n"), false);
+        assert!(single.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(single.contains("Content-Range: bytes 1-3/5\r\n"));
+        assert!(single.ends_with("Content-Length: 3\r\n\r\nell"));
+
+        let multiple = range("Range: bytes=0-0, -2\r\n");
+        let content_type = multiple.headers().get("Content-Type").unwrap().to_string();
+        let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap();
+        let multiple = sent(&multiple, false);
+        let (head, body) = multiple.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+        assert_eq!(
+            body,
+            format!(
+                "\r\n--{boundary}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-0/5\r\n\r\nh\
+                 \r\n--{boundary}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 3-4/5\r\n\r\nlo\
+                 \r\n--{boundary}--\r\n"
+            )
+        );
+
+        let unsatisfiable = range("Range: bytes=10-20\r\n");
+        assert_eq!(unsatisfiable.status_code(), StatusCode::RangeNotSatisfiable);
+        assert_eq!(unsatisfiable.headers().get("Content-Range"), Some("bytes */5"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn stale_if_range_gets_the_whole_file() {
+        let dir = public_dir("if-range");
+        let handler = WebsiteHandler::new(dir.to_string_lossy().into_owned());
+        let range = |if_range: &str| {
+            let raw = format!("GET /files/a.txt HTTP/1.1\r\nRange: bytes=1-3\r\nIf-Range: {if_range}\r\n\r\n");
+            get(&handler, &raw).status_code()
+        };
+        let full = get(&handler, "GET /files/a.txt HTTP/1.1\r\n\r\n");
+        let etag = full.headers().get("ETag").unwrap();
+        let last_modified = full.headers().get("Last-Modified").unwrap();
+
+        assert_eq!(range(etag), StatusCode::PartialContent);
+        assert_eq!(range(last_modified), StatusCode::PartialContent);
+        assert_eq!(range("\"stale\""), StatusCode::OK);
+        assert_eq!(range("Sun, 06 Nov 1994 08:49:37 GMT"), StatusCode::OK);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn file_bodies_reach_the_socket_intact() {
+        let dir = public_dir("socket");
+        let contents: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+        fs::write(dir.join("files/blob.bin"), &contents).unwrap();
+        let handler = WebsiteHandler::new(dir.to_string_lossy().into_owned());
+        let response = get(&handler, "GET /files/blob.bin HTTP/1.1\r\n\r\n");
+        assert!(matches!(response.body(), Some(ResponseBody::File { .. })));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        let writer = thread::spawn(move || response.send(&mut server).unwrap());
+        let mut received = Vec::new();
+        (&client).read_to_end(&mut received).unwrap();
+        writer.join().unwrap();
+        let head_len = Request::head_len(&received).unwrap();
+        let (head, received) = received.split_at(head_len);
+
+        assert!(String::from_utf8_lossy(head).contains("Content-Length: 100000\r\n"));
+        assert_eq!(received, contents);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn serves_precompressed_siblings_unless_a_range_is_asked_for() {
+        let dir = public_dir("precompressed");
+        fs::write(dir.join("files/app.js"), "plain").unwrap();
+        fs::write(dir.join("files/app.js.br"), "brotli").unwrap();
+        fs::write(dir.join("files/app.js.gz"), "gzip").unwrap();
+        let handler = WebsiteHandler::new(dir.to_string_lossy().into_owned());
+        let fetch = |headers: &str| sent(&get(&handler, &format!("GET /files/app.js HTTP/1.1\r\n{headers}\r\n")), false);
+
+        let brotli = fetch("Accept-Encoding: gzip, br\r\n");
+        assert!(brotli.contains("Content-Encoding: br\r\n"));
+        assert!(brotli.contains("Content-Type: text/javascript; charset=utf-8\r\n"));
+        assert!(brotli.contains("Vary: Accept-Encoding\r\n"));
+        assert!(brotli.ends_with("brotli"));
+        assert!(fetch("Accept-Encoding: gzip\r\n").ends_with("\r\n\r\ngzip"));
+
+        let ranged = fetch("Accept-Encoding: br\r\nRange: bytes=0-1\r\n");
+        assert!(ranged.starts_with("HTTP/1.1 206 "));
+        assert!(!ranged.contains("Content-Encoding"));
+        assert!(ranged.ends_with("pl"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,529 @@
+use super::http::{Method, Request, Response, StatusCode, Transport, Upgrade};
+use super::server::Handler;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha1::{Digest, Sha1};
+use std::io::{ErrorKind, Read, Result as IoResult};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Appended to the client's key before hashing, per RFC 6455.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Largest message accepted by default, after reassembling fragments.
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+/// How often `on_tick` runs by default while no frames arrive.
+const DEFAULT_TICK: Duration = Duration::from_secs(1);
+/// How long a connection may stay silent by default before it is pinged.
+/// Twice this without hearing anything, it is dropped.
+const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(30);
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// A close status code from RFC 6455, section 7.4.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CloseCode(pub u16);
+
+impl CloseCode {
+    pub const NORMAL: Self = Self(1000);
+    pub const GOING_AWAY: Self = Self(1001);
+    pub const PROTOCOL_ERROR: Self = Self(1002);
+    pub const UNSUPPORTED_DATA: Self = Self(1003);
+    pub const INVALID_PAYLOAD: Self = Self(1007);
+    pub const POLICY_VIOLATION: Self = Self(1008);
+    pub const MESSAGE_TOO_BIG: Self = Self(1009);
+    pub const INTERNAL_ERROR: Self = Self(1011);
+
+    /// Whether a peer may send this code in a close frame.
+    fn is_allowed(&self) -> bool {
+        matches!(self.0, 1000..=1003 | 1007..=1011 | 3000..=4999)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// What waiting on a `WebSocket` turned up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Incoming {
+    Message(Message),
+    /// Nothing arrived before the timeout.
+    Idle,
+    /// The connection is over. There is no code if it ended without a close
+    /// frame.
+    Closed(Option<CloseCode>, String),
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// The server end of a WebSocket connection.
+///
+/// Pings are answered and close handshakes completed as frames are read;
+/// only whole data messages come out of `recv`.
+pub struct WebSocket<'a> {
+    stream: &'a mut dyn Transport,
+    path: String,
+    max_message_size: usize,
+    buffer: Vec<u8>,
+    fragments: Option<(u8, Vec<u8>)>,
+    close_sent: Option<CloseCode>,
+    last_received: Instant,
+}
+
+impl<'a> WebSocket<'a> {
+    fn new(stream: &'a mut dyn Transport, path: String, max_message_size: usize) -> Self {
+        Self {
+            stream,
+            path,
+            max_message_size,
+            buffer: Vec::new(),
+            fragments: None,
+            close_sent: None,
+            last_received: Instant::now(),
+        }
+    }
+
+    /// The path the connection was opened on.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn send(&mut self, message: Message) -> IoResult<()> {
+        match message {
+            Message::Text(text) => self.write_frame(OP_TEXT, text.as_bytes()),
+            Message::Binary(data) => self.write_frame(OP_BINARY, &data),
+        }
+    }
+
+    pub fn send_text(&mut self, text: &str) -> IoResult<()> {
+        self.write_frame(OP_TEXT, text.as_bytes())
+    }
+
+    pub fn ping(&mut self, payload: &[u8]) -> IoResult<()> {
+        self.write_frame(OP_PING, payload)
+    }
+
+    /// Starts the closing handshake. Nothing more may be sent afterwards.
+    pub fn close(&mut self, code: CloseCode, reason: &str) -> IoResult<()> {
+        if self.close_sent.is_some() {
+            return Ok(());
+        }
+        self.close_sent = Some(code);
+        let mut payload = code.0.to_be_bytes().to_vec();
+        // Control frames carry at most 125 bytes.
+        let reason = truncate_utf8(reason, 123);
+        payload.extend_from_slice(reason.as_bytes());
+        self.write_frame(OP_CLOSE, &payload)
+    }
+
+    /// Waits up to `timeout` for the next message.
+    pub fn recv(&mut self, timeout: Duration) -> Incoming {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match parse_frame(&self.buffer, self.max_message_size) {
+                Ok(Some((frame, len))) => {
+                    self.buffer.drain(..len);
+                    self.last_received = Instant::now();
+                    match self.handle_frame(frame) {
+                        Ok(Some(incoming)) => return incoming,
+                        Ok(None) => continue,
+                        Err((code, reason)) => return self.fail(code, reason),
+                    }
+                }
+                Ok(None) => {}
+                Err((code, reason)) => return self.fail(code, reason),
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || self.stream.socket().set_read_timeout(Some(remaining)).is_err() {
+                return Incoming::Idle;
+            }
+            let mut chunk = [0; 4096];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Incoming::Closed(None, String::new()),
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Incoming::Idle;
+                }
+                Err(_) => return Incoming::Closed(None, String::new()),
+            }
+        }
+    }
+
+    fn handle_frame(&mut self, frame: Frame) -> Result<Option<Incoming>, (CloseCode, &'static str)> {
+        match frame.opcode {
+            OP_PING => {
+                if self.close_sent.is_none() {
+                    let _ = self.write_frame(OP_PONG, &frame.payload);
+                }
+                Ok(None)
+            }
+            OP_PONG => Ok(None),
+            OP_CLOSE => {
+                let (code, reason) = parse_close(&frame.payload)?;
+                if self.close_sent.is_none() {
+                    let _ = self.close(code.unwrap_or(CloseCode::NORMAL), "");
+                }
+                Ok(Some(Incoming::Closed(code, reason)))
+            }
+            OP_TEXT | OP_BINARY if self.fragments.is_some() => {
+                Err((CloseCode::PROTOCOL_ERROR, "expected a continuation frame"))
+            }
+            OP_TEXT | OP_BINARY if !frame.fin => {
+                self.fragments = Some((frame.opcode, frame.payload));
+                Ok(None)
+            }
+            OP_TEXT | OP_BINARY => message(frame.opcode, frame.payload).map(Some),
+            OP_CONTINUATION => {
+                let Some((opcode, mut data)) = self.fragments.take() else {
+                    return Err((CloseCode::PROTOCOL_ERROR, "unexpected continuation frame"));
+                };
+                if data.len() + frame.payload.len() > self.max_message_size {
+                    return Err((CloseCode::MESSAGE_TOO_BIG, "message too big"));
+                }
+                data.extend_from_slice(&frame.payload);
+                if frame.fin {
+                    message(opcode, data).map(Some)
+                } else {
+                    self.fragments = Some((opcode, data));
+                    Ok(None)
+                }
+            }
+            _ => Err((CloseCode::PROTOCOL_ERROR, "unknown opcode")),
+        }
+    }
+
+    /// Closes the connection because the peer broke the protocol.
+    fn fail(&mut self, code: CloseCode, reason: &str) -> Incoming {
+        let _ = self.close(code, reason);
+        Incoming::Closed(Some(code), reason.to_string())
+    }
+
+    /// Server frames are never masked.
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> IoResult<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 10);
+        frame.push(0x80 | opcode);
+        match payload.len() {
+            len @ 0..=125 => frame.push(len as u8),
+            len @ 126..=0xFFFF => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+        self.stream.write_all(&frame)?;
+        self.stream.flush()
+    }
+}
+
+fn message(opcode: u8, data: Vec<u8>) -> Result<Incoming, (CloseCode, &'static str)> {
+    let message = match opcode {
+        OP_TEXT => Message::Text(String::from_utf8(data).map_err(|_| (CloseCode::INVALID_PAYLOAD, "invalid UTF-8"))?),
+        _ => Message::Binary(data),
+    };
+    Ok(Incoming::Message(message))
+}
+
+fn parse_close(payload: &[u8]) -> Result<(Option<CloseCode>, String), (CloseCode, &'static str)> {
+    match payload {
+        [] => Ok((None, String::new())),
+        [_] => Err((CloseCode::PROTOCOL_ERROR, "truncated close code")),
+        [high, low, reason @ ..] => {
+            let code = CloseCode(u16::from_be_bytes([*high, *low]));
+            if !code.is_allowed() {
+                return Err((CloseCode::PROTOCOL_ERROR, "invalid close code"));
+            }
+            let reason = std::str::from_utf8(reason).map_err(|_| (CloseCode::INVALID_PAYLOAD, "invalid UTF-8"))?;
+            Ok((Some(code), reason.to_string()))
+        }
+    }
+}
+
+/// Decodes one client frame from the front of `buf`, returning it and how
+/// many bytes it took, or `None` if more bytes are needed.
+fn parse_frame(buf: &[u8], max_message_size: usize) -> Result<Option<(Frame, usize)>, (CloseCode, &'static str)> {
+    let [first, second, ..] = *buf else {
+        return Ok(None);
+    };
+    let fin = first & 0x80 != 0;
+    let opcode = first & 0x0F;
+    if first & 0x70 != 0 {
+        return Err((CloseCode::PROTOCOL_ERROR, "reserved bits set"));
+    }
+    if second & 0x80 == 0 {
+        return Err((CloseCode::PROTOCOL_ERROR, "client frames must be masked"));
+    }
+
+    let (len, mut offset) = match second & 0x7F {
+        126 => match buf.get(2..4) {
+            Some(bytes) => (u16::from_be_bytes([bytes[0], bytes[1]]) as u64, 4),
+            None => return Ok(None),
+        },
+        127 => match buf.get(2..10) {
+            Some(bytes) => (u64::from_be_bytes(bytes.try_into().unwrap()), 10),
+            None => return Ok(None),
+        },
+        len => (len as u64, 2),
+    };
+    if opcode & 0x08 != 0 && (!fin || len > 125) {
+        return Err((CloseCode::PROTOCOL_ERROR, "invalid control frame"));
+    }
+    if len > max_message_size as u64 {
+        return Err((CloseCode::MESSAGE_TOO_BIG, "message too big"));
+    }
+
+    let Some(mask) = buf.get(offset..offset + 4) else {
+        return Ok(None);
+    };
+    let mask = [mask[0], mask[1], mask[2], mask[3]];
+    offset += 4;
+    let end = offset + len as usize;
+    let Some(payload) = buf.get(offset..end) else {
+        return Ok(None);
+    };
+    let payload = payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]).collect();
+
+    Ok(Some((Frame { fin, opcode, payload }, end)))
+}
+
+fn truncate_utf8(s: &str, max: usize) -> &str {
+    let mut end = s.len().min(max);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// The `Sec-WebSocket-Accept` value answering a `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+    STANDARD.encode(sha1.finalize())
+}
+
+/// Checks that `request` asks for a WebSocket upgrade this server can
+/// speak, returning the `Sec-WebSocket-Accept` value to answer with, or the
+/// response to send instead.
+pub fn handshake(request: &Request) -> Result<String, Response> {
+    let headers = request.headers();
+    let is_upgrade = *request.method() == Method::GET
+        && headers.contains_token("Connection", "upgrade")
+        && headers.get("Upgrade").is_some_and(|upgrade| upgrade.trim().eq_ignore_ascii_case("websocket"));
+    if !is_upgrade {
+        return Err(Response::builder()
+            .status(StatusCode::UpgradeRequired)
+            .header("Upgrade", "websocket")
+            .header("Connection", "Upgrade")
+            .build());
+    }
+    if headers.get("Sec-WebSocket-Version").map(str::trim) != Some("13") {
+        return Err(Response::builder()
+            .status(StatusCode::UpgradeRequired)
+            .header("Sec-WebSocket-Version", "13")
+            .build());
+    }
+    match headers.get("Sec-WebSocket-Key").map(str::trim) {
+        Some(key) if STANDARD.decode(key).is_ok_and(|nonce| nonce.len() == 16) => Ok(accept_key(key)),
+        _ => Err(Response::new(StatusCode::BadRequest, None)),
+    }
+}
+
+/// Handles the connections a `WebSocketEndpoint` accepts. Every callback
+/// for one connection runs on the same worker thread, in order.
+pub trait WebSocketHandler: Send + Sync + 'static {
+    /// Whatever each connection needs to keep track of.
+    type State: Send;
+
+    /// Decides whether to take an upgrade request, e.g. after checking
+    /// `Request::user`; `Err` answers with that response instead.
+    fn accept(&self, request: &Request) -> Result<(), Response> {
+        Ok(())
+    }
+
+    fn on_open(&self, socket: &mut WebSocket) -> Self::State;
+
+    fn on_message(&self, socket: &mut WebSocket, state: &mut Self::State, message: Message);
+
+    /// Runs whenever the connection has been quiet for a tick, which is
+    /// the place to push updates from elsewhere.
+    fn on_tick(&self, socket: &mut WebSocket, state: &mut Self::State) {}
+
+    fn on_close(&self, state: Self::State, code: Option<CloseCode>, reason: &str) {}
+}
+
+/// A `Handler` that upgrades requests to WebSocket connections run by a
+/// `WebSocketHandler`. Connections hold a worker thread while they are
+/// open, so size the server's worker pool for them.
+pub struct WebSocketEndpoint<H> {
+    handler: Arc<H>,
+    max_message_size: usize,
+    tick: Duration,
+    ping_interval: Duration,
+}
+
+impl<H: WebSocketHandler> WebSocketEndpoint<H> {
+    pub fn new(handler: H) -> Self {
+        Self {
+            handler: Arc::new(handler),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            tick: DEFAULT_TICK,
+            ping_interval: DEFAULT_PING_INTERVAL,
+        }
+    }
+
+    /// Connections sending a larger message are closed with 1009.
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    pub fn with_tick(mut self, tick: Duration) -> Self {
+        self.tick = tick;
+        self
+    }
+
+    pub fn with_ping_interval(mut self, ping_interval: Duration) -> Self {
+        self.ping_interval = ping_interval;
+        self
+    }
+}
+
+impl<H: WebSocketHandler> Handler for WebSocketEndpoint<H> {
+    fn handle_request(&self, request: &Request) -> Response {
+        let accept = match handshake(request).and_then(|accept| self.handler.accept(request).map(|_| accept)) {
+            Ok(accept) => accept,
+            Err(response) => return response,
+        };
+
+        let handler = Arc::clone(&self.handler);
+        let path = request.path().to_string();
+        let (max_message_size, tick, ping_interval) = (self.max_message_size, self.tick, self.ping_interval);
+        let upgrade = Upgrade::new(move |stream| {
+            let mut socket = WebSocket::new(stream, path, max_message_size);
+            let mut state = handler.on_open(&mut socket);
+            loop {
+                match socket.recv(tick) {
+                    Incoming::Message(message) => handler.on_message(&mut socket, &mut state, message),
+                    Incoming::Closed(code, reason) => return handler.on_close(state, code, &reason),
+                    // Gave the peer a tick to answer our close.
+                    Incoming::Idle if socket.close_sent.is_some() => {
+                        return handler.on_close(state, socket.close_sent, "");
+                    }
+                    Incoming::Idle => {
+                        let silent = socket.last_received.elapsed();
+                        if silent >= ping_interval * 2 {
+                            return handler.on_close(state, None, "");
+                        }
+                        if silent >= ping_interval {
+                            let _ = socket.ping(b"");
+                        }
+                        handler.on_tick(&mut socket, &mut state);
+                    }
+                }
+            }
+        });
+
+        Response::builder()
+            .status(StatusCode::SwitchingProtocols)
+            .header("Upgrade", "websocket")
+            .header("Connection", "Upgrade")
+            .header("Sec-WebSocket-Accept", accept)
+            .upgrade(upgrade)
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Server;
+    use crate::test_util::start;
+    use std::io::Write;
+    use std::net::TcpStream;
+
+    fn masked(opcode: u8, fin: bool, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![(fin as u8) << 7 | opcode, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    #[test]
+    fn computes_accept_key() {
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn parses_masked_frames_and_rejects_unmasked() {
+        let frame = masked(OP_TEXT, true, b"Hello");
+        let (parsed, len) = parse_frame(&frame, 1024).unwrap().unwrap();
+
+        assert_eq!((parsed.fin, parsed.opcode, &parsed.payload[..], len), (true, OP_TEXT, &b"Hello"[..], frame.len()));
+        assert!(parse_frame(&frame[..4], 1024).unwrap().is_none());
+        assert_eq!(parse_frame(&[0x81, 0x05, b'H'], 1024).err().map(|(code, _)| code), Some(CloseCode::PROTOCOL_ERROR));
+        assert_eq!(parse_frame(&frame, 4).err().map(|(code, _)| code), Some(CloseCode::MESSAGE_TOO_BIG));
+    }
+
+    struct Echo;
+
+    impl WebSocketHandler for Echo {
+        type State = ();
+
+        fn on_open(&self, _: &mut WebSocket) {}
+
+        fn on_message(&self, socket: &mut WebSocket, _: &mut (), message: Message) {
+            socket.send(message).unwrap();
+        }
+    }
+
+    #[test]
+    fn echoes_fragmented_messages_and_closes() {
+        let (addr, shutdown, running) = start(Server::new(String::new()), WebSocketEndpoint::new(Echo));
+        let mut stream = TcpStream::connect(&addr).unwrap();
+
+        stream
+            .write_all(b"GET /ws HTTP/1.1\r\nHost: test\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n")
+            .unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.starts_with("HTTP/1.1 101 "));
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        stream.write_all(&masked(OP_TEXT, false, b"Hel")).unwrap();
+        stream.write_all(&masked(OP_PING, true, b"p")).unwrap();
+        stream.write_all(&masked(OP_CONTINUATION, true, b"lo")).unwrap();
+        let mut reply = [0; 3 + 7];
+        stream.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"\x8a\x01p\x81\x05Hello");
+
+        stream.write_all(&masked(OP_CLOSE, true, &1000u16.to_be_bytes())).unwrap();
+        let mut close = Vec::new();
+        stream.read_to_end(&mut close).unwrap();
+        assert_eq!(close, b"\x88\x02\x03\xe8");
+
+        shutdown.shutdown();
+        running.join().unwrap();
+    }
+}